@@ -3,10 +3,21 @@ use std::path::PathBuf;
 use clap::Parser;
 use url::Url;
 
+use crate::{config::OutputFormat, kmf::GameRef};
+
 #[derive(Debug, Parser)]
 pub struct Cli {
   #[arg(short, long)]
   pub config: Option<PathBuf>,
+  /// Never touch network, install purely from cache
+  #[arg(long, global = true)]
+  pub offline: bool,
+  /// Wait for other running kmf instead of failing
+  #[arg(long, global = true)]
+  pub wait: bool,
+  /// Output format
+  #[arg(long, global = true)]
+  pub output: Option<OutputFormat>,
   #[command(subcommand)]
   pub command: Command,
 }
@@ -15,7 +26,151 @@ pub struct Cli {
 pub enum Command {
   Install {
     url: Vec<Url>,
+    /// Game url, or name of a game added by `kmf game add`
+    #[arg(long)]
+    game: Option<GameRef>,
+    #[command(flatten)]
+    dry_run: DryRunArgs,
+  },
+  /// Update installed mods, all mods of the game when no url is given
+  Update {
+    url: Vec<Url>,
+    /// Game url, or name of a game added by `kmf game add`
+    #[arg(long)]
+    game: Option<GameRef>,
+    #[command(flatten)]
+    dry_run: DryRunArgs,
+  },
+  /// Remove installed mods from the game
+  Uninstall {
+    #[arg(required = true)]
+    url: Vec<Url>,
+    /// Game url, or name of a game added by `kmf game add`
+    #[arg(long)]
+    game: Option<GameRef>,
+    #[command(flatten)]
+    dry_run: DryRunArgs,
+  },
+  /// Check installed mods against their sources, all mods of the game when no url is given
+  Verify {
+    url: Vec<Url>,
+    /// Game url, or name of a game added by `kmf game add`
+    #[arg(long)]
+    game: Option<GameRef>,
+    /// Restore modified and missing files from cache, downloading the mod when needed
+    #[arg(long)]
+    repair: bool,
+  },
+  /// Check the game, cache and network for common problems
+  Doctor {
+    /// Game url, or name of a game added by `kmf game add`
+    #[arg(long)]
+    game: Option<GameRef>,
+  },
+  /// Manage named game installations
+  Game {
+    #[command(subcommand)]
+    command: GameCommand,
+  },
+  /// Show, create, check or edit the config file
+  Config {
+    #[command(subcommand)]
+    command: ConfigCommand,
+  },
+  /// Manage download cache
+  Cache {
+    #[command(subcommand)]
+    command: CacheCommand,
+  },
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DryRunArgs {
+  /// Print what would change in the game without touching it
+  #[arg(long)]
+  pub dry_run: bool,
+  /// Download mods that are not cached or outdated during dry run, planning the newest version
+  #[arg(long, requires = "dry_run")]
+  pub download: bool,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ConfigCommand {
+  /// Print the effective config
+  Show {
+    /// Also print where each value comes from
+    #[arg(long)]
+    origin: bool,
+  },
+  /// Write a config file with default values
+  Init {
+    /// Overwrite the existing config file
+    #[arg(long)]
+    force: bool,
+  },
+  /// Validate the config
+  Check,
+  /// Set a value in the config file, `value` is taken as the type of the field
+  Set {
+    /// Toml dotted key, quote parts containing dots: `network.hosts."example.com".proxy`
+    key: String,
+    value: String,
+  },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum GameCommand {
+  /// Add a game to the config file, `--game <name>` then selects it
+  Add {
+    /// Letters, digits, `-` and `_`
+    name: String,
+    /// Game root, the dir containing `bin/`
+    path: PathBuf,
+    /// Wine prefix the game is installed in, `path` is then a Windows path like
+    /// `C:/Games/Korabli`
+    #[arg(long)]
+    prefix: Option<PathBuf>,
+    /// Realm of the client, e.g. `ru` or `eu`
+    #[arg(long)]
+    realm: Option<String>,
+    /// Client version to use instead of the newest one
+    #[arg(long)]
+    version: Option<String>,
+    /// Profile used for the game by default
+    #[arg(long)]
+    profile: Option<String>,
+    /// Also make it `default_game`
+    #[arg(long)]
+    default: bool,
+    /// Replace the game with the same name
+    #[arg(long)]
+    force: bool,
+  },
+  /// List games of the config
+  List,
+  /// Find game clients in Steam libraries and Wine, Proton or Lutris prefixes
+  Detect,
+  /// Remove a game from the config file, mods installed in it are kept
+  Remove { name: String },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum CacheCommand {
+  /// List cached mods
+  List,
+  /// Show details of a cached mod
+  Info {
+    /// Cache id (or its prefix) or mod url
+    entry: String,
+  },
+  /// Clear cache, everything when no entry is given
+  Clear {
+    /// Only clear the cache of this resolver
     #[arg(long)]
-    game: Option<Url>,
+    resolver: Option<String>,
+    /// Cache ids (or their prefix) or mod urls
+    entry: Vec<String>,
   },
+  /// Remove cached mods not referenced by any installed mod
+  Gc,
 }
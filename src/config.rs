@@ -1,87 +1,610 @@
-use std::path::{Path, PathBuf};
+use std::{
+  collections::BTreeMap,
+  fmt,
+  ops::Range,
+  path::{Path, PathBuf},
+};
 
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use tokio::fs;
-use tracing::{debug, warn};
+use tracing::debug;
+use url::Url;
 
-use crate::cli::Cli;
+use crate::{
+  cli::Cli,
+  i18n::Language,
+  kmf::{GAME_SCHEMES, GameRef, is_game_name, is_windows_path},
+  resolver::BUILTIN_RESOLVERS,
+};
 
-mod error;
+pub mod command;
+pub mod error;
+pub mod layer;
 
 use error::Error;
+use layer::{Layers, Origin, find_project_file};
 
 type Result<T> = std::result::Result<T, Error>;
 
 /// Kmf Config
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+  /// Game url, or name of a game in `games`, used when `--game` is not given
   pub default_game: Option<String>,
+  /// Named game installations, selected with `--game <name>`
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub games: BTreeMap<String, GameConfig>,
   /// Cache directory
   #[serde(default = "default_cache_dir")]
   pub cache_dir: PathBuf,
+  /// Cache size budget in bytes, least recently used entries are evicted
+  /// after each run when exceeded. No limit when unset
+  pub cache_size_limit: Option<u64>,
+  /// Seconds a cached mod is trusted without revalidating against its source.
+  /// Always revalidate when unset
+  pub cache_ttl: Option<u64>,
+  /// Data directory, keeps installed mods
+  #[serde(default = "default_data_dir")]
+  pub data_dir: PathBuf,
   /// Progress draw target
   #[serde(default = "default_progress_draw_target")]
   pub progress_draw_target: ProgressDrawTargetType,
+  /// Never touch network, install purely from cache
+  #[serde(default)]
+  pub offline: bool,
+  /// Wait for other running kmf instead of failing
+  #[serde(default)]
+  pub wait_for_lock: bool,
+  /// Output format
+  #[serde(default)]
+  pub output: OutputFormat,
+  /// Language of messages, follows the system locale when not set
+  pub language: Option<Language>,
+  /// Http client settings
+  #[serde(default)]
+  pub network: NetworkConfig,
+  /// Resolver plugin executables by url scheme, in addition to
+  /// `kmf-resolver-<scheme>` found on `PATH`
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub resolvers: BTreeMap<String, PathBuf>,
+  /// Seconds to wait for each response of a resolver plugin before stopping it
+  #[serde(default = "default_plugin_timeout")]
+  pub plugin_timeout: u64,
+}
+
+/// `[games.<name>]`, a game installation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GameConfig {
+  /// Game root, the dir containing `bin/`. A Windows path like `C:/Games/Korabli` when
+  /// `prefix` is set
+  pub path: PathBuf,
+  /// Wine prefix the game is installed in
+  pub prefix: Option<PathBuf>,
+  /// Realm of the client, e.g. `ru` or `eu`
+  pub realm: Option<String>,
+  /// Client version to use, the newest one when unset
+  pub version: Option<String>,
+  /// Profile used for the game by default
+  pub profile: Option<String>,
+}
+
+impl GameConfig {
+  /// `file` url of the game, or `wine` url when it has a `prefix`, with its preferred `version`
+  pub fn url(&self) -> std::io::Result<Url> {
+    let mut url = match self.prefix.as_ref() {
+      Some(prefix) => {
+        let prefix = Url::from_file_path(std::path::absolute(prefix)?)
+          .expect("absolute path is a valid file url");
+        let mut url = Url::parse("wine:///").expect("wine url is valid");
+        url.set_path(prefix.path());
+        url
+          .query_pairs_mut()
+          .append_pair("path", self.path.to_string_lossy().as_ref());
+        url
+      }
+      None => Url::from_file_path(std::path::absolute(self.path.as_path())?)
+        .expect("absolute path is a valid file url"),
+    };
+    if let Some(version) = self.version.as_ref() {
+      url.query_pairs_mut().append_pair("version", version);
+    }
+    Ok(url)
+  }
+}
+
+/// `[network]`, applied to the http client of every resolver
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+  /// Proxy for all requests: `http://`, `https://`, `socks5://` or `socks5h://`.
+  /// Follows `HTTP_PROXY` and friends when unset
+  pub proxy: Option<String>,
+  /// Hosts never sent through `proxy`, comma separated like `NO_PROXY`
+  pub no_proxy: Option<String>,
+  /// Seconds to wait for a connection
+  pub connect_timeout: Option<u64>,
+  /// Seconds to wait for each read
+  pub read_timeout: Option<u64>,
+  /// Extra PEM root certificates to trust
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub root_certificates: Vec<PathBuf>,
+  pub user_agent: Option<String>,
+  /// Retries of transient failures
+  #[serde(default = "default_retries")]
+  pub retries: u32,
+  /// netrc file with credentials, `$NETRC` or `~/.netrc` when unset.
+  /// Credentials in `hosts` take precedence
+  pub netrc: Option<PathBuf>,
+  /// Overrides by host, each also applies to subdomains of the host
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub hosts: BTreeMap<String, HostConfig>,
+}
+
+impl Default for NetworkConfig {
+  fn default() -> Self {
+    Self {
+      proxy: None,
+      no_proxy: None,
+      connect_timeout: None,
+      read_timeout: None,
+      root_certificates: Vec::new(),
+      user_agent: None,
+      retries: default_retries(),
+      netrc: None,
+      hosts: BTreeMap::new(),
+    }
+  }
+}
+
+/// `[network.hosts."<host>"]`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+  /// Proxy for requests to the host, see [`NetworkConfig::proxy`]
+  pub proxy: Option<String>,
+  /// Seconds a whole request to the host may take
+  pub timeout: Option<u64>,
+  pub user_agent: Option<String>,
+  /// Basic auth user, sent with `password`
+  pub username: Option<String>,
+  pub password: Option<Secret>,
+  /// Bearer token
+  pub token: Option<Secret>,
+  /// Also send credentials over plain http, they only go over https by default
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub allow_http_auth: bool,
+}
+
+/// Credential kept out of logs, `Debug` and `kmf config show`
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+  pub fn new(secret: String) -> Self {
+    Self(secret)
+  }
+
+  pub fn expose(&self) -> &str {
+    self.0.as_str()
+  }
+}
+
+impl fmt::Debug for Secret {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("\"***\"")
+  }
+}
+
+impl Serialize for Secret {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str("***")
+  }
+}
+
+impl HostConfig {
+  /// Whether the config of `pattern` applies to `host`
+  pub fn matches(pattern: &str, host: &str) -> bool {
+    host.eq_ignore_ascii_case(pattern)
+      || host
+        .to_ascii_lowercase()
+        .ends_with(format!(".{}", pattern.to_ascii_lowercase()).as_str())
+  }
 }
 
 impl Default for Config {
   fn default() -> Self {
     Self {
       default_game: None,
+      games: BTreeMap::new(),
       cache_dir: default_cache_dir(),
+      cache_size_limit: None,
+      cache_ttl: None,
+      data_dir: default_data_dir(),
       progress_draw_target: default_progress_draw_target(),
+      offline: false,
+      wait_for_lock: false,
+      output: OutputFormat::default(),
+      language: None,
+      network: NetworkConfig::default(),
+      resolvers: BTreeMap::new(),
+      plugin_timeout: default_plugin_timeout(),
     }
   }
 }
 
 /// Progress draw target type.
 /// Stdout: write progress bar to `stdout`.
+/// Stderr: write progress bar to `stderr`.
 /// Hidden: do not write progress bar.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum ProgressDrawTargetType {
   Stdout,
+  Stderr,
   Hidden,
 }
 
+/// Output format.
+/// Human: progress bars and text.
+/// Json: JSON lines of events on `stdout`, see [`crate::event::Event`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum OutputFormat {
+  #[default]
+  Human,
+  Json,
+}
+
+fn project_dirs() -> ProjectDirs {
+  ProjectDirs::from("com", "zerodegress", "kmf").expect("project dir unavailable")
+}
+
 fn default_cache_dir() -> PathBuf {
-  ProjectDirs::from("com", "zerodegress", "kmf")
-    .expect("project dir unavailable")
-    .cache_dir()
-    .to_path_buf()
+  project_dirs().cache_dir().to_path_buf()
+}
+
+fn default_data_dir() -> PathBuf {
+  project_dirs().data_dir().to_path_buf()
+}
+
+fn default_retries() -> u32 {
+  3
+}
+
+fn default_plugin_timeout() -> u64 {
+  600
 }
 
 fn default_progress_draw_target() -> ProgressDrawTargetType {
   ProgressDrawTargetType::Stdout
 }
 
+/// Turn a toml error at `span` of `content` into an error with line and column
+fn parse_error(path: &Path, content: &str, span: Option<Range<usize>>, message: &str) -> Error {
+  let (line, column) = span
+    .map(|span| {
+      let before = &content[..span.start.min(content.len())];
+      let line_start = before.rfind('\n').map(|x| x + 1).unwrap_or(0);
+      (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+      )
+    })
+    .unwrap_or((0, 0));
+  Error::Parse {
+    path: path.to_path_buf(),
+    line,
+    column,
+    message: message.trim().to_string(),
+  }
+}
+
+/// Check `dir` can be created and written into
+async fn check_writable(key: &'static str, dir: &Path) -> Result<()> {
+  let not_writable = |source| Error::NotWritable {
+    key,
+    path: dir.to_path_buf(),
+    source,
+  };
+  fs::create_dir_all(dir).await.map_err(not_writable)?;
+  let probe = dir.join(".kmf-write-test");
+  fs::write(probe.as_path(), b"")
+    .await
+    .map_err(not_writable)?;
+  fs::remove_file(probe).await.map_err(not_writable)?;
+  Ok(())
+}
+
 impl Config {
-  /// Construct Config from config file
-  pub async fn try_from_config_file(config_file: &Path) -> Result<Self> {
-    let config = fs::read_to_string(config_file).await?;
-    let config = toml::from_str::<Config>(config.as_str()).unwrap_or_else(|err| {
-      warn!("error when deserialize config file: {:?}", err);
-      Config::default()
-    });
-    Ok(config)
+  /// Config file used when `--config` is not given
+  pub fn default_file() -> PathBuf {
+    project_dirs().config_dir().join("config.toml")
   }
 
-  /// Construct Config from cli and config file
-  pub async fn try_from_cli(cli: &Cli) -> Result<Self> {
-    let config = cli
-      .config
-      .to_owned()
-      .map(async |config| Self::try_from_config_file(config.as_path()).await);
-
-    let config = if let Some(config) = config {
-      config.await?
-      // TODO: 此处本应检查config是不是有效的
-    } else {
-      Config::default()
-    };
+  /// Parse config from toml `content` read from `path`
+  pub fn parse(path: &Path, content: &str) -> Result<Self> {
+    toml::from_str::<Config>(content)
+      .map_err(|err| parse_error(path, content, err.span(), err.message()))
+  }
+
+  /// Config file given by cli, or the default one
+  pub fn file_from_cli(cli: &Cli) -> PathBuf {
+    cli.config.to_owned().unwrap_or_else(Self::default_file)
+  }
+
+  /// Construct Config from all layers, without validating it.
+  /// Layers from low to high: defaults, the config file (`--config` or the default one),
+  /// project-local `kmf.toml`, `KMF_*` environment variables and cli flags.
+  pub async fn load_from_cli(cli: &Cli) -> Result<Self> {
+    Self::load_layers(cli).await?.into_config()
+  }
+
+  pub async fn load_layers(cli: &Cli) -> Result<Layers> {
+    let mut layers = Layers::new();
+
+    let config_file = Self::file_from_cli(cli);
+    if cli.config.is_some() || fs::try_exists(config_file.as_path()).await? {
+      layers.merge_file(config_file.as_path()).await?;
+    }
+    if let Some(project_file) = find_project_file(std::env::current_dir()?.as_path()).await?
+      && project_file != config_file
+    {
+      layers.merge_file(project_file.as_path()).await?;
+    }
+    layers.merge_env(std::env::vars())?;
+
+    let mut flags = toml::Table::new();
+    if cli.offline {
+      flags.insert("offline".to_string(), true.into());
+    }
+    if cli.wait {
+      flags.insert("wait_for_lock".to_string(), true.into());
+    }
+    if let Some(output) = cli.output {
+      flags.insert(
+        "output".to_string(),
+        toml::Value::try_from(output).expect("output format is always serializable"),
+      );
+    }
+    layers.merge(flags, Origin::Cli);
 
-    debug!("cache_dir: {:?}", config.cache_dir);
+    debug!("config origins: {:?}", layers.origins());
+    Ok(layers)
+  }
 
+  /// Construct and validate Config from cli and config file
+  pub async fn try_from_cli(cli: &Cli) -> Result<Self> {
+    let config = Self::load_from_cli(cli).await?;
+    config.validate().await?;
     Ok(config)
   }
+
+  /// Check values that cannot be checked by parsing alone
+  pub async fn validate(&self) -> Result<()> {
+    for (name, game) in self.games.iter() {
+      if !is_game_name(name) {
+        return Err(Error::InvalidValue {
+          key: format!("games.{name:?}"),
+          message: "game names may only contain letters, digits, `-` and `_`".to_string(),
+        });
+      }
+      match game.prefix.as_ref() {
+        Some(prefix) if !prefix.is_absolute() => {
+          return Err(Error::InvalidValue {
+            key: format!("games.{name}.prefix"),
+            message: format!("{prefix:?} is not an absolute path"),
+          });
+        }
+        Some(_) if !game.path.to_str().is_some_and(is_windows_path) => {
+          return Err(Error::InvalidValue {
+            key: format!("games.{name}.path"),
+            message: format!(
+              "{:?} is not a windows path like `C:/Games/Korabli`",
+              game.path
+            ),
+          });
+        }
+        None if !game.path.is_absolute() => {
+          return Err(Error::InvalidValue {
+            key: format!("games.{name}.path"),
+            message: if game.path.to_str().is_some_and(is_windows_path) {
+              format!("{:?} is a windows path, set `prefix` too", game.path)
+            } else {
+              format!("{:?} is not an absolute path", game.path)
+            },
+          });
+        }
+        _ => {}
+      }
+    }
+    if let Some(default_game) = self.default_game.as_ref() {
+      let invalid = |reason: String| Error::InvalidDefaultGame {
+        value: default_game.to_owned(),
+        reason,
+      };
+      match default_game
+        .parse()
+        .map_err(|err: url::ParseError| invalid(err.to_string()))?
+      {
+        GameRef::Name(name) => {
+          if !self.games.contains_key(name.as_str()) {
+            return Err(invalid(format!("no game named {name:?} in `games`")));
+          }
+        }
+        GameRef::Url(url) => {
+          if !GAME_SCHEMES.contains(&url.scheme()) {
+            return Err(invalid(format!("unsupported scheme {:?}", url.scheme())));
+          }
+        }
+      }
+    }
+    self.network.validate().await?;
+    for (scheme, program) in self.resolvers.iter() {
+      if BUILTIN_RESOLVERS.contains(&scheme.as_str()) {
+        return Err(Error::InvalidValue {
+          key: format!("resolvers.{scheme}"),
+          message: format!("{scheme:?} is the name of a built-in resolver"),
+        });
+      }
+      which::which(program).map_err(|err| Error::InvalidValue {
+        key: format!("resolvers.{scheme}"),
+        message: format!("{program:?}: {err}"),
+      })?;
+    }
+    check_writable("cache_dir", self.cache_dir.as_path()).await?;
+    check_writable("data_dir", self.data_dir.as_path()).await?;
+    Ok(())
+  }
+}
+
+/// Check `proxy` is an url the http client can use
+fn check_proxy(key: String, proxy: &str) -> Result<()> {
+  let scheme = Url::parse(proxy)
+    .map_err(|err| Error::InvalidValue {
+      key: key.to_owned(),
+      message: err.to_string(),
+    })?
+    .scheme()
+    .to_string();
+  if !PROXY_SCHEMES.contains(&scheme.as_str()) {
+    return Err(Error::InvalidValue {
+      key,
+      message: format!("unsupported proxy scheme {scheme:?}"),
+    });
+  }
+  Ok(())
+}
+
+/// Supported proxy url schemes
+const PROXY_SCHEMES: &[&str] = &["http", "https", "socks5", "socks5h"];
+
+impl NetworkConfig {
+  /// netrc file to read, `None` when there is none
+  pub fn netrc_file(&self) -> Option<PathBuf> {
+    if let Some(netrc) = self.netrc.as_ref() {
+      return Some(netrc.to_owned());
+    }
+    let netrc = std::env::var_os("NETRC").map(PathBuf::from).or_else(|| {
+      directories::BaseDirs::new().map(|x| {
+        x.home_dir()
+          .join(if cfg!(windows) { "_netrc" } else { ".netrc" })
+      })
+    })?;
+    netrc.is_file().then_some(netrc)
+  }
+
+  async fn validate(&self) -> Result<()> {
+    if let Some(proxy) = self.proxy.as_ref() {
+      check_proxy("network.proxy".to_string(), proxy)?;
+    }
+    for (host, host_config) in self.hosts.iter() {
+      if let Some(proxy) = host_config.proxy.as_ref() {
+        check_proxy(format!("network.hosts.{host:?}.proxy"), proxy)?;
+      }
+      if host_config.password.is_some() && host_config.username.is_none() {
+        return Err(Error::InvalidValue {
+          key: format!("network.hosts.{host:?}"),
+          message: "`password` needs a `username`".to_string(),
+        });
+      }
+      if host_config.token.is_some()
+        && (host_config.username.is_some() || host_config.password.is_some())
+      {
+        return Err(Error::InvalidValue {
+          key: format!("network.hosts.{host:?}"),
+          message: "use either `token` or `username` and `password`".to_string(),
+        });
+      }
+    }
+    if let Some(netrc) = self.netrc.as_ref()
+      && !fs::try_exists(netrc).await?
+    {
+      return Err(Error::InvalidValue {
+        key: "network.netrc".to_string(),
+        message: format!("{netrc:?} does not exist"),
+      });
+    }
+    for root_certificate in self.root_certificates.iter() {
+      if !fs::try_exists(root_certificate).await? {
+        return Err(Error::InvalidValue {
+          key: "network.root_certificates".to_string(),
+          message: format!("{root_certificate:?} does not exist"),
+        });
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn game(path: &str, prefix: Option<&str>) -> GameConfig {
+    GameConfig {
+      path: PathBuf::from(path),
+      prefix: prefix.map(PathBuf::from),
+      realm: None,
+      version: Some("123".to_string()),
+      profile: None,
+    }
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn game_urls() {
+    assert_eq!(
+      game("/home/u/My Games/Korabli", None)
+        .url()
+        .unwrap()
+        .as_str(),
+      "file:///home/u/My%20Games/Korabli?version=123"
+    );
+    let url = game("C:/Games/Korabli", Some("/home/u/.wine"))
+      .url()
+      .unwrap();
+    assert_eq!(url.scheme(), "wine");
+    assert_eq!(url.to_file_path().unwrap(), PathBuf::from("/home/u/.wine"));
+    assert_eq!(
+      url.query_pairs().collect::<Vec<_>>(),
+      [
+        ("path".into(), "C:/Games/Korabli".into()),
+        ("version".into(), "123".into())
+      ]
+    );
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn validates_game_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let validate = |game: GameConfig| {
+      let mut config = Config {
+        cache_dir: dir.path().join("cache"),
+        data_dir: dir.path().join("data"),
+        ..Config::default()
+      };
+      config.games.insert("main".to_string(), game);
+      async move { config.validate().await }
+    };
+    assert!(validate(game("/games/Korabli", None)).await.is_ok());
+    assert!(
+      validate(game("C:/Games/Korabli", Some("/home/u/.wine")))
+        .await
+        .is_ok()
+    );
+    for (path, prefix) in [
+      ("games/Korabli", None),
+      ("C:/Games/Korabli", None),
+      ("/games/Korabli", Some("/home/u/.wine")),
+      ("C:/Games/Korabli", Some(".wine")),
+    ] {
+      assert!(
+        validate(game(path, prefix)).await.is_err(),
+        "{path} {prefix:?}"
+      );
+    }
+  }
 }
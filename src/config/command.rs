@@ -0,0 +1,476 @@
+use std::path::Path;
+
+use tokio::fs;
+
+use crate::{
+  cli::{Cli, ConfigCommand, GameCommand},
+  config::OutputFormat,
+  event::{Event, EventReporter},
+  i18n::Msg,
+  kmf::{GameInstall, detect_games, unix_path},
+};
+
+use super::{
+  Config, Result,
+  error::Error,
+  layer::{Origin, flatten},
+  parse_error,
+};
+
+/// Run `kmf config` commands, they work on the config file and never need a valid config
+pub async fn run(cli: &Cli, command: &ConfigCommand, output: OutputFormat) -> Result<()> {
+  let reporter = match output {
+    OutputFormat::Human => EventReporter::default(),
+    OutputFormat::Json => EventReporter::json(),
+  };
+  let path = Config::file_from_cli(cli);
+  match command {
+    ConfigCommand::Show { origin } => {
+      reporter.report(Event::TaskStarted {
+        task: "config_show".to_string(),
+      });
+      let layers = Config::load_layers(cli).await?;
+      let origins = layers.origins().to_owned();
+      let config = layers.into_config()?;
+      if output == OutputFormat::Human {
+        if *origin {
+          let toml::Value::Table(table) = toml::Value::try_from(&config)? else {
+            unreachable!("config is a table")
+          };
+          for (key, value) in flatten(&table) {
+            println!(
+              "{key} = {value}  # {}",
+              origins.get(key.as_str()).unwrap_or(&Origin::Default)
+            );
+          }
+        } else {
+          print!("{}", toml::to_string_pretty(&config)?);
+        }
+      }
+      reporter.report(Event::Config {
+        path,
+        config: serde_json::to_value(&config).expect("config is always serializable"),
+        origins: origin.then_some(origins),
+      });
+    }
+    ConfigCommand::Init { force } => {
+      reporter.report(Event::TaskStarted {
+        task: "config_init".to_string(),
+      });
+      if !force && fs::try_exists(path.as_path()).await? {
+        return Err(Error::AlreadyExists { path });
+      }
+      write(
+        path.as_path(),
+        toml::to_string_pretty(&Config::default())?.as_str(),
+      )
+      .await?;
+      if output == OutputFormat::Human {
+        println!("{}", Msg::ConfigWritten { path: &path });
+      }
+    }
+    ConfigCommand::Check => {
+      reporter.report(Event::TaskStarted {
+        task: "config_check".to_string(),
+      });
+      Config::try_from_cli(cli).await?;
+      if output == OutputFormat::Human {
+        println!("{}", Msg::ConfigOk { path: &path });
+      }
+    }
+    ConfigCommand::Set { key, value } => {
+      reporter.report(Event::TaskStarted {
+        task: "config_set".to_string(),
+      });
+      set(path.as_path(), key, value).await?;
+      if output == OutputFormat::Human {
+        println!("{}", Msg::ConfigWritten { path: &path });
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Run `kmf game` commands, they work on `[games]` of the config file
+pub async fn run_game(cli: &Cli, command: &GameCommand, output: OutputFormat) -> Result<()> {
+  let reporter = match output {
+    OutputFormat::Human => EventReporter::default(),
+    OutputFormat::Json => EventReporter::json(),
+  };
+  let path = Config::file_from_cli(cli);
+  match command {
+    GameCommand::Add {
+      name,
+      path: game_path,
+      prefix,
+      realm,
+      version,
+      profile,
+      default,
+      force,
+    } => {
+      reporter.report(Event::TaskStarted {
+        task: "game_add".to_string(),
+      });
+      let key = format!("games.{name}");
+      let invalid = |key: String, message: String| Error::InvalidValue { key, message };
+      let message = |err: crate::kmf::error::Error| {
+        err
+          .message()
+          .map(|x| x.to_string())
+          .unwrap_or_else(|| err.to_string())
+      };
+      let prefix = prefix.as_deref().map(std::path::absolute).transpose()?;
+      // wine 前缀中的游戏按 Windows 路径记录
+      let (game_path, game_root) = match prefix.as_ref() {
+        Some(prefix) => {
+          let path = game_path
+            .to_str()
+            .ok_or_else(|| invalid(format!("{key}.path"), "not valid UTF-8".to_string()))?;
+          let game_root = unix_path(prefix.as_path(), path)
+            .await
+            .map_err(|err| invalid(format!("{key}.path"), message(err)))?;
+          (path.to_string(), game_root)
+        }
+        None => {
+          let game_root = std::path::absolute(game_path)?;
+          let path = game_root
+            .to_str()
+            .ok_or_else(|| invalid(format!("{key}.path"), "not valid UTF-8".to_string()))?
+            .to_string();
+          (path, game_root)
+        }
+      };
+      let install = GameInstall::read(game_root.as_path())
+        .await
+        .map_err(|err| {
+          invalid(
+            format!("{key}.path"),
+            format!("{game_root:?}: {}", message(err)),
+          )
+        })?;
+      // 客户端版本只对应最后安装的构建，游戏更新后会指向别的构建，记录构建号
+      let version = version
+        .as_ref()
+        .map(|version| {
+          install
+            .find_build(version)
+            .map(|x| x.build.to_owned())
+            .ok_or_else(|| {
+              invalid(
+                format!("{key}.version"),
+                format!("no version {version:?} in {game_root:?}"),
+              )
+            })
+        })
+        .transpose()?;
+      let prefix = prefix
+        .as_ref()
+        .map(|x| {
+          x.to_str()
+            .ok_or_else(|| invalid(format!("{key}.prefix"), "not valid UTF-8".to_string()))
+        })
+        .transpose()?;
+
+      edit(path.as_path(), key.as_str(), |document| {
+        let games = document
+          .entry("games")
+          .or_insert_with(|| {
+            let mut games = toml_edit::Table::new();
+            games.set_implicit(true);
+            toml_edit::Item::Table(games)
+          })
+          .as_table_mut()
+          .ok_or_else(|| invalid("games".to_string(), "not a table".to_string()))?;
+        if !force && games.contains_key(name) {
+          return Err(Error::GameExists {
+            name: name.to_owned(),
+          });
+        }
+        let mut game = toml_edit::Table::new();
+        game.insert("path", toml_edit::value(game_path.as_str()));
+        if let Some(prefix) = prefix {
+          game.insert("prefix", toml_edit::value(prefix));
+        }
+        // 没有指定区服时使用游戏元数据中的
+        let realm = realm.as_ref().or(install.realm.as_ref());
+        for (key, value) in [
+          ("realm", realm),
+          ("version", version.as_ref()),
+          ("profile", profile.as_ref()),
+        ] {
+          if let Some(value) = value {
+            game.insert(key, toml_edit::value(value));
+          }
+        }
+        games.insert(name, toml_edit::Item::Table(game));
+        if *default {
+          document.insert("default_game", toml_edit::value(name));
+        }
+        Ok(())
+      })
+      .await?;
+      if output == OutputFormat::Human {
+        println!("{}", Msg::ConfigWritten { path: &path });
+      }
+    }
+    GameCommand::List => {
+      reporter.report(Event::TaskStarted {
+        task: "game_list".to_string(),
+      });
+      let config = Config::load_from_cli(cli).await?;
+      for (name, game) in config.games {
+        let default = config.default_game.as_ref() == Some(&name);
+        if output == OutputFormat::Human {
+          let prefix = game.prefix.as_ref().map(|x| x.display().to_string());
+          let details = [
+            ("prefix", prefix.as_ref()),
+            ("realm", game.realm.as_ref()),
+            ("version", game.version.as_ref()),
+            ("profile", game.profile.as_ref()),
+          ]
+          .into_iter()
+          .filter_map(|(key, value)| Some(format!("{key}: {}", value?)))
+          .collect::<Vec<_>>();
+          print!(
+            "{} {name} {}",
+            if default { "*" } else { " " },
+            game.path.display()
+          );
+          if details.is_empty() {
+            println!();
+          } else {
+            println!(" ({})", details.join(", "));
+          }
+        }
+        reporter.report(Event::Game {
+          name,
+          path: game.path,
+          prefix: game.prefix,
+          realm: game.realm,
+          version: game.version,
+          profile: game.profile,
+          default,
+        });
+      }
+    }
+    GameCommand::Detect => {
+      reporter.report(Event::TaskStarted {
+        task: "game_detect".to_string(),
+      });
+      let games = detect_games().await;
+      if output == OutputFormat::Human && games.is_empty() {
+        println!("{}", Msg::NoGameDetected);
+      }
+      for game in games {
+        if output == OutputFormat::Human {
+          println!(
+            "{}",
+            Msg::GameDetected {
+              game_root: game.install.game_root.as_path(),
+              source: game.source.name(),
+              realm: game.install.realm.as_deref(),
+              versions: game.install.builds_display().as_str(),
+            }
+          );
+        }
+        reporter.report(Event::GameDetected {
+          game_root: game.install.game_root,
+          source: game.source,
+          id: game.install.id,
+          realm: game.install.realm,
+          builds: game.install.builds,
+        });
+      }
+    }
+    GameCommand::Remove { name } => {
+      reporter.report(Event::TaskStarted {
+        task: "game_remove".to_string(),
+      });
+      edit(
+        path.as_path(),
+        format!("games.{name}").as_str(),
+        |document| {
+          let removed = document
+            .get_mut("games")
+            .and_then(|x| x.as_table_like_mut())
+            .and_then(|x| x.remove(name));
+          if removed.is_none() {
+            return Err(Error::GameNotFound {
+              name: name.to_owned(),
+            });
+          }
+          // 不能留下指向已删除游戏的 default_game
+          if document.get("default_game").and_then(|x| x.as_str()) == Some(name.as_str()) {
+            document.remove("default_game");
+          }
+          Ok(())
+        },
+      )
+      .await?;
+      if output == OutputFormat::Human {
+        println!("{}", Msg::ConfigWritten { path: &path });
+      }
+    }
+  }
+  Ok(())
+}
+
+async fn write(path: &Path, content: &str) -> Result<()> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).await?;
+  }
+  fs::write(path, content).await?;
+  Ok(())
+}
+
+/// Set `key` (a toml dotted key, e.g. `network.hosts."example.com".proxy`) to `value`, keeping
+/// the rest of the file as is. `value` is parsed as toml when the field takes that type, and
+/// taken as a string otherwise, so `25.3` stays a string for string fields.
+async fn set(path: &Path, key: &str, value: &str) -> Result<()> {
+  let mut keys = toml_edit::Key::parse(key).map_err(|err| Error::InvalidValue {
+    key: key.to_string(),
+    message: err.message().trim().to_string(),
+  })?;
+  // 解析出的键带着命令行里的空白，写入文件时按默认格式
+  keys.iter_mut().for_each(toml_edit::Key::fmt);
+  let mut candidates = value
+    .parse::<toml_edit::Value>()
+    .ok()
+    .into_iter()
+    .collect::<Vec<_>>();
+  if candidates.iter().all(|x| !x.is_str()) {
+    candidates.push(value.into());
+  }
+  let mut first_err = None;
+  for value in candidates {
+    // 字段类型不符时换下一种解释，其他错误直接报告
+    match edit(path, key, |document| {
+      insert(document, keys.as_slice(), value)
+    })
+    .await
+    {
+      Err(err @ Error::InvalidValue { .. }) => {
+        first_err.get_or_insert(err);
+      }
+      result => return result,
+    }
+  }
+  Err(first_err.expect("there is at least one candidate"))
+}
+
+/// Insert `value` at `keys`, creating the tables on the way
+fn insert(
+  document: &mut toml_edit::DocumentMut,
+  keys: &[toml_edit::Key],
+  value: toml_edit::Value,
+) -> Result<()> {
+  let (last, keys) = keys.split_last().expect("parsed keys are never empty");
+  let mut table = document.as_table_mut();
+  for (i, key) in keys.iter().enumerate() {
+    table = table
+      .entry_format(key)
+      .or_insert_with(toml_edit::table)
+      .as_table_mut()
+      .ok_or_else(|| Error::InvalidValue {
+        key: keys[..=i]
+          .iter()
+          .map(|x| x.display_repr().into_owned())
+          .collect::<Vec<_>>()
+          .join("."),
+        message: "not a table".to_string(),
+      })?;
+  }
+  table.insert_formatted(last, toml_edit::value(value));
+  Ok(())
+}
+
+/// Edit the config file with `edit`, keeping the rest of the file as is. The result is
+/// validated before it is written, errors are reported for `key`
+async fn edit(
+  path: &Path,
+  key: &str,
+  edit: impl FnOnce(&mut toml_edit::DocumentMut) -> Result<()>,
+) -> Result<()> {
+  let content = if fs::try_exists(path).await? {
+    fs::read_to_string(path).await?
+  } else {
+    String::new()
+  };
+  let mut document = content
+    .parse::<toml_edit::DocumentMut>()
+    .map_err(|err| parse_error(path, content.as_str(), err.span(), err.message()))?;
+  edit(&mut document)?;
+
+  let content = document.to_string();
+  let config = toml::from_str::<Config>(content.as_str()).map_err(|err| Error::InvalidValue {
+    key: key.to_string(),
+    message: err.message().trim().to_string(),
+  })?;
+  config.validate().await?;
+  write(path, content.as_str()).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  async fn config_file(dir: &Path) -> std::path::PathBuf {
+    let path = dir.join("kmf.toml");
+    let content = format!(
+      "cache_dir = {:?}\ndata_dir = {:?}\n\n[games.main]\npath = {:?}\n",
+      dir.join("cache"),
+      dir.join("data"),
+      dir.join("game"),
+    );
+    fs::write(path.as_path(), content).await.unwrap();
+    path
+  }
+
+  #[tokio::test]
+  async fn sets_quoted_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = config_file(dir.path()).await;
+
+    set(
+      path.as_path(),
+      r#"network.hosts."example.com".proxy"#,
+      "socks5://127.0.0.1:1080",
+    )
+    .await
+    .unwrap();
+    let content = fs::read_to_string(path).await.unwrap();
+    assert!(content.contains("proxy = \"socks5://127.0.0.1:1080\""));
+    let config = toml::from_str::<Config>(&content).unwrap();
+    assert_eq!(
+      config.network.hosts["example.com"].proxy.as_deref(),
+      Some("socks5://127.0.0.1:1080")
+    );
+  }
+
+  #[tokio::test]
+  async fn coerces_values_to_the_field_type() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = config_file(dir.path()).await;
+
+    set(path.as_path(), "games.main.version", "25.3")
+      .await
+      .unwrap();
+    set(path.as_path(), "cache_ttl", "3600").await.unwrap();
+    set(path.as_path(), "offline", "true").await.unwrap();
+    let config =
+      toml::from_str::<Config>(&fs::read_to_string(path.as_path()).await.unwrap()).unwrap();
+    assert_eq!(config.games["main"].version.as_deref(), Some("25.3"));
+    assert_eq!(config.cache_ttl, Some(3600));
+    assert!(config.offline);
+
+    assert!(matches!(
+      set(path.as_path(), "cache_ttl", "soon").await,
+      Err(Error::InvalidValue { .. })
+    ));
+    assert!(matches!(
+      set(path.as_path(), "games.main.version.x", "1").await,
+      Err(Error::InvalidValue { .. })
+    ));
+  }
+}
@@ -1,7 +1,73 @@
+use std::path::PathBuf;
+
+use crate::i18n::Msg;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
   #[error("io: {0}")]
   Io(#[from] std::io::Error),
-  #[error("toml::de: {0}")]
-  TomlDe(#[from] toml::de::Error),
+  #[error("{path:?}:{line}:{column}: {message}")]
+  Parse {
+    path: PathBuf,
+    line: usize,
+    column: usize,
+    message: String,
+  },
+  #[error("toml::ser: {0}")]
+  TomlSer(#[from] toml::ser::Error),
+  #[error("invalid 'default_game' {value:?}: {reason}")]
+  InvalidDefaultGame { value: String, reason: String },
+  #[error("'{key}' {path:?} is not writable")]
+  NotWritable {
+    key: &'static str,
+    path: PathBuf,
+    source: std::io::Error,
+  },
+  #[error("invalid value for '{key}': {message}")]
+  InvalidValue { key: String, message: String },
+  #[error("config file already exists: {path:?}")]
+  AlreadyExists { path: PathBuf },
+  #[error("game already exists: {name}")]
+  GameExists { name: String },
+  #[error("game not found: {name}")]
+  GameNotFound { name: String },
+}
+
+impl Error {
+  /// Localized message, `None` for errors only meaningful in English
+  pub fn message(&self) -> Option<Msg<'_>> {
+    match self {
+      Error::Parse {
+        path,
+        line,
+        column,
+        message,
+      } => Some(Msg::ConfigParse {
+        path,
+        line: *line,
+        column: *column,
+        message,
+      }),
+      Error::InvalidDefaultGame { value, reason } => {
+        Some(Msg::InvalidDefaultGameReason { value, reason })
+      }
+      Error::NotWritable { key, path, .. } => Some(Msg::NotWritable { key, path }),
+      Error::InvalidValue { key, message } => Some(Msg::InvalidConfigValue { key, message }),
+      Error::AlreadyExists { path } => Some(Msg::ConfigExists { path }),
+      Error::GameExists { name } => Some(Msg::GameExists { name }),
+      Error::GameNotFound { name } => Some(Msg::GameNotFound { name }),
+      Error::Io(_) | Error::TomlSer(_) => None,
+    }
+  }
+
+  /// What the user may do about the error
+  pub fn hint(&self) -> Msg<'static> {
+    match self {
+      Error::InvalidDefaultGame { .. } => Msg::HintInvalidDefaultGame,
+      Error::NotWritable { .. } => Msg::HintNotWritable,
+      Error::AlreadyExists { .. } | Error::GameExists { .. } => Msg::HintForce,
+      Error::GameNotFound { .. } => Msg::HintGameList,
+      _ => Msg::HintConfig,
+    }
+  }
 }
@@ -0,0 +1,440 @@
+use std::{
+  collections::BTreeMap,
+  fmt,
+  path::{Path, PathBuf},
+};
+
+use serde::{
+  Deserialize, Deserializer, Serialize, Serializer,
+  de::{self, Visitor},
+};
+use tokio::fs;
+use tracing::warn;
+
+use crate::kmf::is_windows_path;
+
+use super::{Config, Result, error::Error, parse_error};
+
+/// Prefix of environment variables overriding config values,
+/// `KMF_CACHE_DIR` sets `cache_dir` and `KMF_A__B` sets `a.b`
+pub const ENV_PREFIX: &str = "KMF_";
+
+/// Project-local config file, looked up from the current dir upwards
+pub const PROJECT_FILE: &str = "kmf.toml";
+
+/// Where a config value comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+  Default,
+  File(PathBuf),
+  Env(String),
+  Cli,
+}
+
+impl fmt::Display for Origin {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Origin::Default => write!(f, "default"),
+      Origin::File(path) => write!(f, "file {}", path.display()),
+      Origin::Env(name) => write!(f, "env {name}"),
+      Origin::Cli => write!(f, "cli"),
+    }
+  }
+}
+
+impl Serialize for Origin {
+  fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+/// Config values merged from layers, later layers override earlier ones
+#[derive(Debug, Default)]
+pub struct Layers {
+  table: toml::Table,
+  /// Origin of each value by dotted key
+  origins: BTreeMap<String, Origin>,
+}
+
+fn merge(
+  into: &mut toml::Table,
+  from: toml::Table,
+  prefix: &str,
+  origin: &Origin,
+  origins: &mut BTreeMap<String, Origin>,
+) {
+  for (key, value) in from {
+    let dotted = if prefix.is_empty() {
+      key.to_owned()
+    } else {
+      format!("{prefix}.{key}")
+    };
+    match (into.get_mut(key.as_str()), value) {
+      (Some(toml::Value::Table(into)), toml::Value::Table(from)) => {
+        merge(into, from, dotted.as_str(), origin, origins);
+      }
+      (_, value) => {
+        origins.retain(|x, _| x != &dotted && !x.starts_with(format!("{dotted}.").as_str()));
+        flatten_origins(&value, dotted.as_str(), origin, origins);
+        into.insert(key, value);
+      }
+    }
+  }
+}
+
+fn flatten_origins(
+  value: &toml::Value,
+  dotted: &str,
+  origin: &Origin,
+  origins: &mut BTreeMap<String, Origin>,
+) {
+  match value {
+    toml::Value::Table(table) => {
+      for (key, value) in table {
+        flatten_origins(value, format!("{dotted}.{key}").as_str(), origin, origins);
+      }
+    }
+    _ => {
+      origins.insert(dotted.to_string(), origin.to_owned());
+    }
+  }
+}
+
+/// Leaf values of `table` by dotted key
+pub fn flatten(table: &toml::Table) -> BTreeMap<String, toml::Value> {
+  fn inner(table: &toml::Table, prefix: &str, values: &mut BTreeMap<String, toml::Value>) {
+    for (key, value) in table {
+      let dotted = if prefix.is_empty() {
+        key.to_owned()
+      } else {
+        format!("{prefix}.{key}")
+      };
+      match value {
+        toml::Value::Table(table) => inner(table, dotted.as_str(), values),
+        value => {
+          values.insert(dotted, value.to_owned());
+        }
+      }
+    }
+  }
+  let mut values = BTreeMap::new();
+  inner(table, "", &mut values);
+  values
+}
+
+/// Top-level keys of [`Config`], as asked for by its `Deserialize` impl
+fn config_keys() -> &'static [&'static str] {
+  struct Keys<'a>(&'a mut &'static [&'static str]);
+
+  impl<'de> Deserializer<'de> for Keys<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> std::result::Result<V::Value, Self::Error> {
+      Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+      self,
+      _: &'static str,
+      fields: &'static [&'static str],
+      _: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+      *self.0 = fields;
+      Err(de::Error::custom("only the fields are needed"))
+    }
+
+    serde::forward_to_deserialize_any! {
+      bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+      unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+  }
+
+  let mut keys: &'static [&'static str] = &[];
+  let _ = Config::deserialize(Keys(&mut keys));
+  keys
+}
+
+/// Make relative paths in `table` of a config file relative to `dir`, the dir of the file
+fn resolve_paths(table: &mut toml::Table, dir: &Path) {
+  let resolve = |value: &mut toml::Value| {
+    if let toml::Value::String(path) = value
+      && Path::new(path.as_str()).is_relative()
+      && !is_windows_path(path.as_str())
+    {
+      *path = dir.join(path.as_str()).to_string_lossy().into_owned();
+    }
+  };
+  fn tables(value: Option<&mut toml::Value>) -> impl Iterator<Item = &mut toml::Value> {
+    value
+      .and_then(toml::Value::as_table_mut)
+      .into_iter()
+      .flat_map(|x| x.iter_mut().map(|(_, value)| value))
+  }
+
+  table.get_mut("cache_dir").into_iter().for_each(resolve);
+  table.get_mut("data_dir").into_iter().for_each(resolve);
+  for game in tables(table.get_mut("games")) {
+    game.get_mut("path").into_iter().for_each(resolve);
+    game.get_mut("prefix").into_iter().for_each(resolve);
+  }
+  // 不含路径分隔符的是 PATH 中的程序名
+  for program in tables(table.get_mut("resolvers")) {
+    if program
+      .as_str()
+      .is_some_and(|x| x.contains(std::path::is_separator))
+    {
+      resolve(program);
+    }
+  }
+  if let Some(network) = table.get_mut("network").and_then(toml::Value::as_table_mut) {
+    network.get_mut("netrc").into_iter().for_each(resolve);
+    if let Some(certificates) = network
+      .get_mut("root_certificates")
+      .and_then(toml::Value::as_array_mut)
+    {
+      certificates.iter_mut().for_each(resolve);
+    }
+  }
+}
+
+/// Parse `value` as a toml value, taken as a string when it is not valid toml
+fn parse_value(value: &str) -> toml::Value {
+  toml::Value::deserialize(toml::de::ValueDeserializer::new(value))
+    .unwrap_or_else(|_| toml::Value::String(value.to_string()))
+}
+
+impl Layers {
+  pub fn new() -> Self {
+    let mut layers = Self::default();
+    let toml::Value::Table(defaults) =
+      toml::Value::try_from(Config::default()).expect("config is always serializable")
+    else {
+      unreachable!("config is a table")
+    };
+    layers.merge(defaults, Origin::Default);
+    layers
+  }
+
+  pub fn merge(&mut self, table: toml::Table, origin: Origin) {
+    merge(&mut self.table, table, "", &origin, &mut self.origins);
+  }
+
+  /// Merge the config file, the whole file must be a valid config on its own.
+  /// Relative paths in it are relative to its dir
+  pub async fn merge_file(&mut self, path: &Path) -> Result<()> {
+    let content = fs::read_to_string(path).await?;
+    Config::parse(path, content.as_str())?;
+    let mut table = toml::from_str::<toml::Table>(content.as_str())
+      .map_err(|err| parse_error(path, content.as_str(), err.span(), err.message()))?;
+    if let Some(dir) = std::path::absolute(path)?.parent() {
+      resolve_paths(&mut table, dir);
+    }
+    self.merge(table, Origin::File(path.to_path_buf()));
+    Ok(())
+  }
+
+  /// Merge `KMF_*` variables from `vars`, variables not naming a config key such as `KMF_LOG`
+  /// are skipped
+  pub fn merge_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+    let mut vars = vars
+      .into_iter()
+      .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+      .collect::<Vec<_>>();
+    vars.sort();
+    let config_keys = config_keys();
+    for (name, value) in vars {
+      let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+      let top_key = key.split('.').next().expect("split always yields one item");
+      if !config_keys.contains(&top_key) {
+        warn!("ignore {name}, {top_key:?} is not a config key");
+        continue;
+      }
+      let mut table = toml::Table::new();
+      let mut keys = key.split('.').rev();
+      table.insert(
+        keys
+          .next()
+          .expect("split always yields one item")
+          .to_string(),
+        parse_value(value.as_str()),
+      );
+      for key in keys {
+        let mut parent = toml::Table::new();
+        parent.insert(key.to_string(), toml::Value::Table(table));
+        table = parent;
+      }
+      Config::deserialize(toml::Value::Table(table.to_owned())).map_err(|err| {
+        Error::InvalidValue {
+          key: name.to_owned(),
+          message: err.message().trim().to_string(),
+        }
+      })?;
+      self.merge(table, Origin::Env(name));
+    }
+    Ok(())
+  }
+
+  pub fn origins(&self) -> &BTreeMap<String, Origin> {
+    &self.origins
+  }
+
+  pub fn into_config(self) -> Result<Config> {
+    Config::deserialize(toml::Value::Table(self.table)).map_err(|err| Error::InvalidValue {
+      key: "config".to_string(),
+      message: err.message().trim().to_string(),
+    })
+  }
+}
+
+/// `kmf.toml` in `dir` or its nearest ancestor
+pub async fn find_project_file(dir: &Path) -> Result<Option<PathBuf>> {
+  for dir in dir.ancestors() {
+    let path = dir.join(PROJECT_FILE);
+    if fs::try_exists(path.as_path()).await? {
+      return Ok(Some(path));
+    }
+  }
+  Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars
+      .iter()
+      .map(|(name, value)| (name.to_string(), value.to_string()))
+      .collect()
+  }
+
+  #[test]
+  fn config_keys_are_fields() {
+    let keys = config_keys();
+    for key in ["cache_dir", "games", "network", "resolvers", "language"] {
+      assert!(keys.contains(&key), "{key} missing in {keys:?}");
+    }
+  }
+
+  #[test]
+  fn merge_env_values() {
+    let mut layers = Layers::new();
+    layers
+      .merge_env(vars(&[
+        ("KMF_CACHE_TTL", "3600"),
+        ("KMF_OFFLINE", "true"),
+        ("KMF_NETWORK__RETRIES", "5"),
+        ("KMF_NETWORK__USER_AGENT", "kmf test"),
+        ("CACHE_TTL", "1"),
+      ]))
+      .unwrap();
+    assert_eq!(
+      layers.origins().get("network.retries"),
+      Some(&Origin::Env("KMF_NETWORK__RETRIES".to_string()))
+    );
+    let config = layers.into_config().unwrap();
+    assert_eq!(config.cache_ttl, Some(3600));
+    assert!(config.offline);
+    assert_eq!(config.network.retries, 5);
+    assert_eq!(config.network.user_agent.as_deref(), Some("kmf test"));
+  }
+
+  #[test]
+  fn merge_env_overrides_file() {
+    let mut layers = Layers::new();
+    layers.merge(
+      toml::from_str("cache_ttl = 60\n[network]\nretries = 1").unwrap(),
+      Origin::File(PathBuf::from("/kmf.toml")),
+    );
+    layers
+      .merge_env(vars(&[("KMF_CACHE_TTL", "3600")]))
+      .unwrap();
+    assert_eq!(
+      layers.origins().get("network.retries"),
+      Some(&Origin::File(PathBuf::from("/kmf.toml")))
+    );
+    let config = layers.into_config().unwrap();
+    assert_eq!(config.cache_ttl, Some(3600));
+    assert_eq!(config.network.retries, 1);
+  }
+
+  #[test]
+  fn merge_env_skips_unknown_keys() {
+    let mut layers = Layers::new();
+    let origins = layers.origins().to_owned();
+    layers
+      .merge_env(vars(&[("KMF_LOG", "debug"), ("KMF_HOME", "/home/kmf")]))
+      .unwrap();
+    assert_eq!(layers.origins(), &origins);
+    layers.into_config().unwrap();
+  }
+
+  #[test]
+  fn merge_env_rejects_invalid_values() {
+    let mut layers = Layers::new();
+    let err = layers
+      .merge_env(vars(&[("KMF_CACHE_TTL", "soon")]))
+      .unwrap_err();
+    assert!(
+      matches!(&err, Error::InvalidValue { key, .. } if key == "KMF_CACHE_TTL"),
+      "{err:?}"
+    );
+    let err = layers
+      .merge_env(vars(&[("KMF_NETWORK__NOPE", "1")]))
+      .unwrap_err();
+    assert!(
+      matches!(&err, Error::InvalidValue { key, .. } if key == "KMF_NETWORK__NOPE"),
+      "{err:?}"
+    );
+  }
+
+  #[tokio::test]
+  async fn merge_file_resolves_relative_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(PROJECT_FILE);
+    std::fs::write(
+      path.as_path(),
+      r#"
+cache_dir = "cache"
+data_dir = "/var/lib/kmf"
+
+[games.main]
+path = "games/Korabli"
+
+[games.wine]
+path = "C:/Games/Korabli"
+prefix = "prefixes/korabli"
+
+[resolvers]
+local = "bin/kmf-resolver-local"
+path = "kmf-resolver-path"
+
+[network]
+root_certificates = ["certs/ca.pem"]
+"#,
+    )
+    .unwrap();
+
+    let mut layers = Layers::new();
+    layers.merge_file(path.as_path()).await.unwrap();
+    let config = layers.into_config().unwrap();
+    let dir = std::path::absolute(dir.path()).unwrap();
+    assert_eq!(config.cache_dir, dir.join("cache"));
+    assert_eq!(config.data_dir, PathBuf::from("/var/lib/kmf"));
+    assert_eq!(config.games["main"].path, dir.join("games/Korabli"));
+    assert_eq!(config.games["wine"].path, PathBuf::from("C:/Games/Korabli"));
+    assert_eq!(
+      config.games["wine"].prefix,
+      Some(dir.join("prefixes/korabli"))
+    );
+    assert_eq!(
+      config.resolvers["local"],
+      dir.join("bin/kmf-resolver-local")
+    );
+    assert_eq!(config.resolvers["path"], PathBuf::from("kmf-resolver-path"));
+    assert_eq!(
+      config.network.root_certificates,
+      vec![dir.join("certs/ca.pem")]
+    );
+  }
+}
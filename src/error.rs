@@ -1,6 +1,122 @@
+use std::{error::Error as _, fmt::Write};
+
+use serde::Serialize;
+
+use crate::{config, i18n::Msg, kmf};
+
 /// Kmf Error
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
   #[error("IO error: {0}")]
   Io(#[from] std::io::Error),
+  #[error("config: {0}")]
+  Config(#[from] config::error::Error),
+  #[error("{0}")]
+  Kmf(#[from] kmf::error::Error),
+}
+
+/// Category of an error, scripts can branch on its exit code.
+///
+/// | Exit code | Category    |
+/// |-----------|-------------|
+/// | 1         | `Other`     |
+/// | 2         | `Usage`     |
+/// | 3         | `Config`    |
+/// | 4         | `Network`   |
+/// | 5         | `NotFound`  |
+/// | 6         | `Conflict`  |
+/// | 7         | `Integrity` |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+  /// Invalid cli arguments, reported by the argument parser
+  Usage,
+  /// Invalid config, game path or cli arguments
+  Config,
+  /// Network unreachable or server error
+  Network,
+  /// Mod, game version, resolver or cache entry not found
+  NotFound,
+  /// Another kmf is running
+  Conflict,
+  /// Downloaded or cached content is corrupt
+  Integrity,
+  Other,
+}
+
+impl ErrorCategory {
+  /// Category of a failed http request, `NotFound` when the server says the url is gone
+  pub fn of_http(status: Option<reqwest::StatusCode>) -> Self {
+    match status {
+      Some(reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE) => ErrorCategory::NotFound,
+      _ => ErrorCategory::Network,
+    }
+  }
+
+  pub fn exit_code(&self) -> u8 {
+    match self {
+      ErrorCategory::Other => 1,
+      ErrorCategory::Usage => 2,
+      ErrorCategory::Config => 3,
+      ErrorCategory::Network => 4,
+      ErrorCategory::NotFound => 5,
+      ErrorCategory::Conflict => 6,
+      ErrorCategory::Integrity => 7,
+    }
+  }
+}
+
+impl Error {
+  pub fn category(&self) -> ErrorCategory {
+    match self {
+      Error::Io(_) => ErrorCategory::Other,
+      Error::Config(_) => ErrorCategory::Config,
+      Error::Kmf(err) => err.category(),
+    }
+  }
+
+  /// Localized message, `None` for errors only meaningful in English
+  pub fn message(&self) -> Option<Msg<'_>> {
+    match self {
+      Error::Io(_) => None,
+      Error::Config(err) => err.message(),
+      Error::Kmf(err) => err.message(),
+    }
+  }
+
+  /// What the user may do about the error
+  pub fn hint(&self) -> Option<Msg<'static>> {
+    match self {
+      Error::Io(_) => None,
+      Error::Config(err) => Some(err.hint()),
+      Error::Kmf(err) => err.hint(),
+    }
+  }
+
+  /// Human readable report in the current language: the error, what caused it and a hint
+  pub fn report(&self) -> String {
+    let mut report = format!(
+      "{}: {}",
+      Msg::Error,
+      self
+        .message()
+        .map(|x| x.to_string())
+        .unwrap_or_else(|| self.to_string())
+    );
+    let mut reported = self.to_string();
+    let mut source = self.source();
+    while let Some(err) = source {
+      // 大部分错误信息已经包含了来源的信息
+      let message = err.to_string();
+      if !reported.contains(message.as_str()) {
+        write!(report, "\n  {}: {}", Msg::CausedBy, message).expect("write to string");
+        reported = message;
+      }
+      source = err.source();
+    }
+    if let Some(hint) = self.hint() {
+      write!(report, "\n  {}: {}", Msg::Hint, hint).expect("write to string");
+    }
+    report
+  }
 }
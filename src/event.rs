@@ -0,0 +1,249 @@
+use std::{collections::BTreeMap, fmt, path::PathBuf, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+  config::layer::Origin,
+  error::ErrorCategory,
+  i18n::{Language, Msg},
+  kmf::{GameBuild, GameSource},
+};
+
+mod json;
+mod progress;
+
+pub use json::JsonSink;
+pub use progress::ProgressSink;
+
+/// Something that happened while kmf is working
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+  TaskStarted {
+    task: String,
+  },
+  TaskFinished {
+    task: String,
+    ok: bool,
+  },
+  Resolved {
+    url: Url,
+    id: String,
+    version: Option<String>,
+    size: Option<u64>,
+  },
+  DownloadProgress {
+    url: Url,
+    downloaded: u64,
+    total: Option<u64>,
+  },
+  Installed {
+    url: Url,
+    game_root: PathBuf,
+    version: String,
+    /// Installed files, relative to `res_mods`
+    files: Vec<PathBuf>,
+  },
+  /// Changes a dry run would make, paths are relative to `res_mods`
+  Plan {
+    task: String,
+    url: Url,
+    game_root: PathBuf,
+    version: String,
+    create: Vec<PathBuf>,
+    overwrite: Vec<PathBuf>,
+    remove: Vec<PathBuf>,
+    conflicts: Vec<Conflict>,
+  },
+  Uninstalled {
+    url: Url,
+    game_root: PathBuf,
+    version: String,
+    /// Removed files, relative to `res_mods`
+    files: Vec<PathBuf>,
+  },
+  /// Result of verifying an installed mod, paths are relative to `res_mods`
+  Verified {
+    url: Url,
+    game_root: PathBuf,
+    version: String,
+    modified: Vec<PathBuf>,
+    missing: Vec<PathBuf>,
+    unexpected: Vec<PathBuf>,
+    /// Files that cannot be checked, installed by old kmf and not in cache
+    unknown: Vec<PathBuf>,
+    /// Modified and missing files were restored
+    repaired: bool,
+  },
+  /// Result of a `kmf doctor` check
+  Check {
+    check: String,
+    status: CheckStatus,
+    message: String,
+    /// How to fix it
+    fix: Option<String>,
+  },
+  Warning {
+    #[serde(skip)]
+    warning: Warning,
+    /// English message
+    message: String,
+  },
+  /// Effective config
+  Config {
+    /// Config file
+    path: PathBuf,
+    config: serde_json::Value,
+    /// Where each value comes from by dotted key, with `--origin`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origins: Option<BTreeMap<String, Origin>>,
+  },
+  /// Named game of config
+  Game {
+    name: String,
+    path: PathBuf,
+    prefix: Option<PathBuf>,
+    realm: Option<String>,
+    version: Option<String>,
+    profile: Option<String>,
+    /// It is `default_game`
+    default: bool,
+  },
+  /// Game client found by `kmf game detect`
+  GameDetected {
+    game_root: PathBuf,
+    source: GameSource,
+    /// Launcher id of the game
+    id: Option<String>,
+    realm: Option<String>,
+    /// Newest first
+    builds: Vec<GameBuild>,
+  },
+  CacheEntry {
+    resolver: String,
+    id: String,
+    url: Url,
+    path: PathBuf,
+    size: u64,
+    last_updated: Option<DateTime<Utc>>,
+    last_used: DateTime<Utc>,
+    /// Game roots the cached mod is installed in
+    installed_in: Vec<PathBuf>,
+  },
+  CacheRemoved {
+    resolver: String,
+    id: String,
+    url: Url,
+    size: u64,
+  },
+  CacheCleared {
+    resolver: String,
+  },
+  Error {
+    message: String,
+    category: ErrorCategory,
+    exit_code: u8,
+    hint: Option<String>,
+  },
+  /// Always the last event
+  Summary {
+    ok: bool,
+    exit_code: u8,
+    tasks: usize,
+  },
+}
+
+/// Outcome of a `kmf doctor` check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+  Ok,
+  Warning,
+  Error,
+  /// Not checked, e.g. when offline
+  Skipped,
+}
+
+/// File installed by more than one mod
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+  /// Relative to `res_mods`
+  pub path: PathBuf,
+  /// The other mod
+  pub url: Url,
+}
+
+impl Event {
+  pub fn warning(warning: Warning) -> Self {
+    Self::Warning {
+      message: warning.msg().localize(Language::En),
+      warning,
+    }
+  }
+}
+
+/// Something the user should know about, but the task goes on
+#[derive(Debug, Clone)]
+pub enum Warning {
+  /// Dry run without `--download` and the mod is not cached
+  NotInCache { url: Url },
+  /// Dry run without `--download` and the cached mod is outdated, the plan is for the cached one
+  UpdatePending { url: Url },
+  /// Files installed by an old kmf are unknown and the mod is not cached
+  UnknownFiles { url: Url, game_root: PathBuf },
+  /// Cache of the mod differs from what was installed, so `verify --repair` left it alone
+  RepairSourceChanged { url: Url, files: Vec<PathBuf> },
+}
+
+impl Warning {
+  pub fn msg(&self) -> Msg<'_> {
+    match self {
+      Warning::NotInCache { url } => Msg::NotInCache { url },
+      Warning::UpdatePending { url } => Msg::UpdatePending { url },
+      Warning::UnknownFiles { url, game_root } => Msg::UnknownFiles { url, game_root },
+      Warning::RepairSourceChanged { url, .. } => Msg::RepairSourceChanged { url },
+    }
+  }
+}
+
+/// Receives events, e.g. to draw progress bars or write them out
+pub trait EventSink: Send + Sync {
+  fn event(&self, event: &Event);
+}
+
+impl<F: Fn(&Event) + Send + Sync> EventSink for F {
+  fn event(&self, event: &Event) {
+    self(event)
+  }
+}
+
+/// Where events are reported to, does nothing by default
+#[derive(Clone, Default)]
+pub struct EventReporter(Option<Arc<dyn EventSink>>);
+
+impl EventReporter {
+  pub fn new(sink: impl EventSink + 'static) -> Self {
+    Self(Some(Arc::new(sink)))
+  }
+
+  /// Write each event as a JSON line to stdout
+  pub fn json() -> Self {
+    Self::new(JsonSink)
+  }
+
+  pub fn report(&self, event: Event) {
+    if let Some(sink) = self.0.as_ref() {
+      sink.event(&event)
+    }
+  }
+}
+
+impl fmt::Debug for EventReporter {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("EventReporter")
+      .field(&self.0.as_ref().map(|_| ".."))
+      .finish()
+  }
+}
@@ -0,0 +1,14 @@
+use super::{Event, EventSink};
+
+/// Writes each event as a JSON line to stdout
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonSink;
+
+impl EventSink for JsonSink {
+  fn event(&self, event: &Event) {
+    println!(
+      "{}",
+      serde_json::to_string(event).expect("event is always serializable")
+    )
+  }
+}
@@ -0,0 +1,107 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use url::Url;
+
+use crate::i18n::Msg;
+
+use super::{Event, EventSink};
+
+/// Draws progress bars of downloads and prints what is done, for human output
+#[derive(Debug)]
+pub struct ProgressSink {
+  multi_progress: MultiProgress,
+  /// Bars of mods being cached
+  bars: Mutex<HashMap<Url, ProgressBar>>,
+}
+
+impl ProgressSink {
+  pub fn new(draw_target: ProgressDrawTarget) -> Self {
+    Self {
+      multi_progress: MultiProgress::with_draw_target(draw_target),
+      bars: Mutex::new(HashMap::new()),
+    }
+  }
+
+  fn bar(&self, url: &Url) -> ProgressBar {
+    self
+      .bars
+      .lock()
+      .expect("progress bars lock poisoned")
+      .entry(url.to_owned())
+      .or_insert_with(|| {
+        let pb = self.multi_progress.add(ProgressBar::new_spinner());
+        pb.enable_steady_tick(Duration::from_millis(100));
+        pb.set_message(format!("{} {url}", Msg::Caching));
+        pb
+      })
+      .to_owned()
+  }
+
+  fn finish(&self, url: &Url) {
+    if let Some(pb) = self
+      .bars
+      .lock()
+      .expect("progress bars lock poisoned")
+      .remove(url)
+    {
+      pb.finish_and_clear();
+    }
+  }
+
+  fn println(&self, line: String) {
+    // 隐藏时 MultiProgress 不输出任何内容
+    let _ = self.multi_progress.println(line);
+  }
+}
+
+impl EventSink for ProgressSink {
+  fn event(&self, event: &Event) {
+    match event {
+      Event::Resolved { url, size, .. } => {
+        let pb = self.bar(url);
+        if let Some(size) = size {
+          pb.set_length(*size);
+        }
+      }
+      Event::DownloadProgress {
+        url,
+        downloaded,
+        total,
+      } => {
+        let pb = self.bar(url);
+        if let Some(total) = total {
+          pb.set_length(*total);
+        }
+        pb.set_style(
+          ProgressStyle::with_template("{spinner} {msg} {bytes}/{total_bytes} {wide_bar}")
+            .expect("progress template is valid"),
+        );
+        pb.set_position(*downloaded);
+      }
+      Event::Installed { url, .. } => {
+        self.finish(url);
+        self.println(format!("{} {url}", Msg::InstallDone));
+      }
+      Event::Uninstalled { url, .. } => {
+        self.finish(url);
+        self.println(format!("{} {url}", Msg::UninstallDone));
+      }
+      Event::Plan { url, .. } => self.finish(url),
+      Event::Warning { warning, .. } => self
+        .multi_progress
+        .suspend(|| eprintln!("{}: {}", Msg::Warning, warning.msg())),
+      Event::TaskFinished { .. } => {
+        for (_, pb) in self
+          .bars
+          .lock()
+          .expect("progress bars lock poisoned")
+          .drain()
+        {
+          pb.finish_and_clear();
+        }
+      }
+      _ => {}
+    }
+  }
+}
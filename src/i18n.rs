@@ -0,0 +1,1072 @@
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+  sync::OnceLock,
+};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Language of messages shown to users
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Language {
+  #[serde(rename = "en")]
+  En,
+  #[serde(rename = "zh-CN")]
+  ZhCn,
+  #[serde(rename = "ru")]
+  Ru,
+}
+
+impl Language {
+  /// Language of a BCP 47 locale like `zh-CN` or `ru_RU.UTF-8`, English when unsupported
+  pub fn from_locale(locale: &str) -> Self {
+    let locale = locale.to_ascii_lowercase();
+    if locale.starts_with("zh") {
+      Language::ZhCn
+    } else if locale.starts_with("ru") {
+      Language::Ru
+    } else {
+      Language::En
+    }
+  }
+
+  pub fn from_system() -> Self {
+    sys_locale::get_locale()
+      .map(|x| Self::from_locale(x.as_str()))
+      .unwrap_or(Language::En)
+  }
+}
+
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+/// Set the language once, the system one is used when `None`
+pub fn set_language(language: Option<Language>) {
+  let _ = LANGUAGE.set(language.unwrap_or_else(Language::from_system));
+}
+
+/// Current language, the system one if not set
+pub fn language() -> Language {
+  *LANGUAGE.get_or_init(Language::from_system)
+}
+
+/// Message catalog, displayed in the current language
+#[derive(Debug, Clone, Copy)]
+pub enum Msg<'a> {
+  // 进度
+  Caching,
+  InstallDone,
+  UninstallDone,
+
+  // 输出
+  Error,
+  CausedBy,
+  Hint,
+  Warning,
+  PlanHeader {
+    task: &'a str,
+    url: &'a Url,
+    game_root: &'a Path,
+    version: &'a str,
+  },
+  NothingToDo,
+  FilesIntact,
+  RepairSourceChanged {
+    url: &'a Url,
+  },
+  Repaired {
+    files: usize,
+  },
+  AlsoInstalledBy {
+    path: &'a Path,
+    url: &'a Url,
+  },
+  NotInCache {
+    url: &'a Url,
+  },
+  UpdatePending {
+    url: &'a Url,
+  },
+  UnknownFiles {
+    url: &'a Url,
+    game_root: &'a Path,
+  },
+  CacheRemoved {
+    resolver: &'a str,
+    url: &'a Url,
+  },
+  CacheCleared {
+    resolver: &'a str,
+  },
+  CacheFreed {
+    size: &'a str,
+  },
+  /// Header of `kmf cache list`, columns line up with its rows
+  CacheListHeader,
+  CacheInfo {
+    id: &'a str,
+    resolver: &'a str,
+    url: &'a Url,
+    path: &'a Path,
+    size: &'a str,
+    last_updated: Option<&'a str>,
+    last_used: &'a str,
+    installed_in: &'a [PathBuf],
+  },
+  ConfigOk {
+    path: &'a Path,
+  },
+  ConfigWritten {
+    path: &'a Path,
+  },
+  GameDetected {
+    game_root: &'a Path,
+    source: &'a str,
+    realm: Option<&'a str>,
+    versions: &'a str,
+  },
+  NoGameDetected,
+  DoctorNoGame,
+  DoctorGameRoot {
+    game_root: &'a Path,
+    versions: &'a str,
+  },
+  DoctorGameRootFailed {
+    game_root: &'a Path,
+    error: &'a str,
+  },
+  DoctorGameFailed {
+    error: &'a str,
+  },
+  DoctorGameUrlFailed {
+    url: &'a Url,
+    error: &'a str,
+  },
+  DoctorWritable {
+    path: &'a Path,
+  },
+  DoctorNotWritable {
+    path: &'a Path,
+    error: &'a str,
+  },
+  DoctorInstalledRecords {
+    mods: usize,
+  },
+  DoctorCorruptStore {
+    path: &'a Path,
+    error: &'a str,
+  },
+  DoctorCacheRecords {
+    resolver: &'a str,
+    entries: usize,
+  },
+  DoctorCacheRecordsFailed {
+    resolver: &'a str,
+    error: &'a str,
+  },
+  DoctorMissingCache {
+    resolver: &'a str,
+    entries: usize,
+  },
+  DoctorNoLeftovers,
+  DoctorLeftovers {
+    files: usize,
+    size: &'a str,
+  },
+  DoctorOffline,
+  DoctorStationReachable {
+    url: &'a str,
+  },
+  DoctorStationUnreachable {
+    url: &'a str,
+    error: &'a str,
+  },
+  DoctorClockOk,
+  DoctorClockSkew {
+    seconds: i64,
+  },
+
+  // 错误
+  GameNotSpecified,
+  GameNotFound {
+    name: &'a str,
+  },
+  GameExists {
+    name: &'a str,
+  },
+  GameNotDetected,
+  InvalidFileUrl {
+    url: &'a Url,
+  },
+  InvalidWineUrl {
+    url: &'a Url,
+  },
+  InvalidWindowsPath {
+    path: &'a str,
+  },
+  DriveNotMapped {
+    drive: &'a str,
+    prefix: &'a Path,
+  },
+  AmbiguousGame {
+    game_roots: &'a [PathBuf],
+  },
+  InvalidDefaultGame {
+    value: &'a str,
+  },
+  InvalidDefaultGameReason {
+    value: &'a str,
+    reason: &'a str,
+  },
+  UnsupportedGameScheme {
+    scheme: &'a str,
+  },
+  VersionNotFound {
+    version: &'a str,
+  },
+  VersionsNotSupported {
+    resolver: &'a str,
+    url: &'a Url,
+  },
+  NoGameVersion {
+    game_root: &'a Path,
+  },
+  GameDirIsNotADir,
+  IllegalGameDirStructure,
+  ModNotFound,
+  ResolverNotFound {
+    resolver: &'a str,
+  },
+  CacheEntryNotFound {
+    entry: &'a str,
+  },
+  ModNotInstalled {
+    url: &'a Url,
+    game_root: &'a Path,
+  },
+  Locked {
+    path: &'a Path,
+  },
+  CannotResolve,
+  NotAvailableOffline {
+    url: &'a Url,
+  },
+  DigestMismatch {
+    url: &'a Url,
+    expected: &'a str,
+    actual: &'a str,
+  },
+  ConfigParse {
+    path: &'a Path,
+    line: usize,
+    column: usize,
+    message: &'a str,
+  },
+  NotWritable {
+    key: &'a str,
+    path: &'a Path,
+  },
+  InvalidConfigValue {
+    key: &'a str,
+    message: &'a str,
+  },
+  ConfigExists {
+    path: &'a Path,
+  },
+  Drifted {
+    mods: usize,
+  },
+  ChecksFailed {
+    failed: usize,
+  },
+
+  // 提示
+  HintConfig,
+  HintGameNotSpecified,
+  HintGameList,
+  HintGameNotDetected,
+  HintAmbiguousGame,
+  HintFileUrl,
+  HintWineUrl,
+  HintDosDevices,
+  HintInvalidDefaultGame,
+  HintUnsupportedGameScheme,
+  HintGameDirStructure,
+  HintVersionNotFound,
+  HintResolverNotFound,
+  HintCacheEntryNotFound,
+  HintWait,
+  HintCannotResolve,
+  HintOffline,
+  HintCorruptDownload,
+  HintInvalidZip,
+  HintNetwork,
+  HintUrlNotFound,
+  HintNotWritable,
+  HintForce,
+  HintRepair,
+  HintGamePermissions,
+  HintCorruptStore {
+    path: &'a Path,
+  },
+  HintClearCache {
+    resolver: &'a str,
+  },
+  HintLeftovers {
+    dir: &'a Path,
+  },
+  HintClock,
+}
+
+/// Columns take two cells for CJK characters
+fn display_width(text: &str) -> usize {
+  text
+    .chars()
+    .map(|x| {
+      if ('\u{2e80}'..='\u{9fff}').contains(&x) {
+        2
+      } else {
+        1
+      }
+    })
+    .sum()
+}
+
+/// `text` padded with spaces on the right to `width` columns
+fn pad(text: &str, width: usize) -> String {
+  format!(
+    "{text}{}",
+    " ".repeat(width.saturating_sub(display_width(text)))
+  )
+}
+
+fn pick(language: Language, en: &str, zh_cn: &str, ru: &str) -> String {
+  match language {
+    Language::En => en,
+    Language::ZhCn => zh_cn,
+    Language::Ru => ru,
+  }
+  .to_string()
+}
+
+impl Msg<'_> {
+  pub fn localize(&self, language: Language) -> String {
+    use Language::*;
+
+    match *self {
+      Msg::Caching => pick(language, "Caching", "缓存中", "Кэширование"),
+      Msg::InstallDone => pick(language, "Installed", "安装完成", "Установлено"),
+      Msg::UninstallDone => pick(language, "Uninstalled", "卸载完成", "Удалено"),
+
+      Msg::Error => pick(language, "error", "错误", "ошибка"),
+      Msg::CausedBy => pick(language, "caused by", "原因", "причина"),
+      Msg::Hint => pick(language, "hint", "提示", "подсказка"),
+      Msg::Warning => pick(language, "warning", "警告", "предупреждение"),
+      Msg::PlanHeader {
+        task,
+        url,
+        game_root,
+        version,
+      } => {
+        let game_root = game_root.display();
+        match language {
+          En => format!("{task} {url} in {game_root} ({version})"),
+          ZhCn => format!("{task} {url}，游戏 {game_root}（{version}）"),
+          Ru => format!("{task} {url} в {game_root} ({version})"),
+        }
+      }
+      Msg::NothingToDo => pick(language, "nothing to do", "无需更改", "нечего делать"),
+      Msg::FilesIntact => pick(
+        language,
+        "all files intact",
+        "文件完好",
+        "все файлы в порядке",
+      ),
+      Msg::RepairSourceChanged { url } => match language {
+        En => {
+          format!("cache of {url} differs from the installed version, not repaired; reinstall it")
+        }
+        ZhCn => format!("{url} 的缓存与已安装的版本不同，未修复，请重新安装"),
+        Ru => format!(
+          "кэш {url} отличается от установленной версии, не восстановлено; переустановите мод"
+        ),
+      },
+      Msg::Repaired { files } => match language {
+        En => format!("restored {files} files"),
+        ZhCn => format!("已恢复 {files} 个文件"),
+        Ru => format!("восстановлено файлов: {files}"),
+      },
+      Msg::AlsoInstalledBy { path, url } => {
+        let path = path.display();
+        match language {
+          En => format!("{path} is also installed by {url}"),
+          ZhCn => format!("{path} 也由 {url} 安装"),
+          Ru => format!("{path} также установлен модом {url}"),
+        }
+      }
+      Msg::NotInCache { url } => match language {
+        En => format!("{url} is not in cache, pass `--download` to see its files"),
+        ZhCn => format!("{url} 不在缓存中，加上 `--download` 以查看其文件"),
+        Ru => format!("{url} нет в кэше, добавьте `--download`, чтобы увидеть его файлы"),
+      },
+      Msg::UpdatePending { url } => match language {
+        En => format!(
+          "{url} has an update, the plan is for the cached copy; pass `--download` to plan the update"
+        ),
+        ZhCn => format!("{url} 有更新，计划基于缓存中的旧版本；加上 `--download` 以查看更新的计划"),
+        Ru => format!(
+          "для {url} есть обновление, план составлен по копии в кэше; добавьте `--download`, чтобы увидеть план обновления"
+        ),
+      },
+      Msg::UnknownFiles { url, game_root } => {
+        let game_root = game_root.display();
+        match language {
+          En => format!("files of {url} are unknown, remove them from {game_root} by hand"),
+          ZhCn => format!("{url} 的文件未知，请手动从 {game_root} 中删除"),
+          Ru => format!("файлы {url} неизвестны, удалите их из {game_root} вручную"),
+        }
+      }
+      Msg::CacheRemoved { resolver, url } => match language {
+        En => format!("removed {resolver} {url}"),
+        ZhCn => format!("已删除 {resolver} {url}"),
+        Ru => format!("удалено {resolver} {url}"),
+      },
+      Msg::CacheCleared { resolver } => match language {
+        En => format!("cleared {resolver}"),
+        ZhCn => format!("已清空 {resolver}"),
+        Ru => format!("очищено {resolver}"),
+      },
+      Msg::CacheFreed { size } => match language {
+        En => format!("freed {size}"),
+        ZhCn => format!("已释放 {size}"),
+        Ru => format!("освобождено {size}"),
+      },
+      Msg::CacheListHeader => {
+        let [resolver, id, size, last_used, url] = match language {
+          En => ["RESOLVER", "ID", "SIZE", "LAST USED", "URL"],
+          ZhCn => ["解析器", "ID", "大小", "最后使用", "URL"],
+          Ru => ["РЕЗОЛВЕР", "ID", "РАЗМЕР", "ИСПОЛЬЗОВАН", "URL"],
+        };
+        format!(
+          "{}{}{}{size}  {}{url}",
+          pad(resolver, 10),
+          pad(id, 14),
+          " ".repeat(12usize.saturating_sub(display_width(size))),
+          pad(last_used, 18),
+        )
+      }
+      Msg::CacheInfo {
+        id,
+        resolver,
+        url,
+        path,
+        size,
+        last_updated,
+        last_used,
+        installed_in,
+      } => {
+        let labels = match language {
+          En => [
+            "id",
+            "resolver",
+            "url",
+            "path",
+            "size",
+            "last updated",
+            "last used",
+            "installed in",
+          ],
+          ZhCn => [
+            "ID",
+            "解析器",
+            "URL",
+            "路径",
+            "大小",
+            "最后更新",
+            "最后使用",
+            "安装于",
+          ],
+          Ru => [
+            "id",
+            "резолвер",
+            "url",
+            "путь",
+            "размер",
+            "обновлён",
+            "использован",
+            "установлен в",
+          ],
+        };
+        let width = labels.iter().map(|x| display_width(x)).max().unwrap_or(0) + 2;
+        let last_updated = last_updated.map_or_else(
+          || pick(language, "unknown", "未知", "неизвестно"),
+          Into::into,
+        );
+        let path = path.display().to_string();
+        let values = [
+          id,
+          resolver,
+          url.as_str(),
+          path.as_str(),
+          size,
+          last_updated.as_str(),
+          last_used,
+        ];
+        let mut lines = labels
+          .iter()
+          .zip(values)
+          .map(|(label, value)| format!("{}{value}", pad(format!("{label}:").as_str(), width)))
+          .collect::<Vec<_>>();
+        lines.extend(installed_in.iter().map(|game_root| {
+          format!(
+            "{}{}",
+            pad(format!("{}:", labels[7]).as_str(), width),
+            game_root.display()
+          )
+        }));
+        lines.join("\n")
+      }
+
+      Msg::ConfigOk { path } => {
+        let path = path.display();
+        match language {
+          En => format!("config {path} is valid"),
+          ZhCn => format!("配置 {path} 有效"),
+          Ru => format!("конфигурация {path} корректна"),
+        }
+      }
+      Msg::ConfigWritten { path } => {
+        let path = path.display();
+        match language {
+          En => format!("wrote {path}"),
+          ZhCn => format!("已写入 {path}"),
+          Ru => format!("записано в {path}"),
+        }
+      }
+      Msg::GameDetected {
+        game_root,
+        source,
+        realm,
+        versions,
+      } => {
+        let game_root = game_root.display();
+        match (language, realm) {
+          (En, Some(realm)) => {
+            format!("{game_root} ({source}, realm {realm}), versions {versions}")
+          }
+          (En, None) => format!("{game_root} ({source}), versions {versions}"),
+          (ZhCn, Some(realm)) => format!("{game_root}（{source}，区服 {realm}），版本：{versions}"),
+          (ZhCn, None) => format!("{game_root}（{source}），版本：{versions}"),
+          (Ru, Some(realm)) => {
+            format!("{game_root} ({source}, регион {realm}), версии: {versions}")
+          }
+          (Ru, None) => format!("{game_root} ({source}), версии: {versions}"),
+        }
+      }
+      Msg::NoGameDetected => pick(
+        language,
+        "no game detected",
+        "未找到游戏",
+        "игры не найдены",
+      ),
+      Msg::DoctorNoGame => pick(
+        language,
+        "no game given, game checks skipped",
+        "未指定游戏，跳过游戏检查",
+        "игра не указана, проверки игры пропущены",
+      ),
+      Msg::DoctorGameRoot {
+        game_root,
+        versions,
+      } => {
+        let game_root = game_root.display();
+        match language {
+          En => format!("game {game_root} has versions {versions}"),
+          ZhCn => format!("游戏 {game_root} 的版本：{versions}"),
+          Ru => format!("версии игры {game_root}: {versions}"),
+        }
+      }
+      Msg::DoctorGameRootFailed { game_root, error } => {
+        let game_root = game_root.display();
+        match language {
+          En => format!("game {game_root} is not usable: {error}"),
+          ZhCn => format!("游戏 {game_root} 不可用：{error}"),
+          Ru => format!("игра {game_root} недоступна: {error}"),
+        }
+      }
+      Msg::DoctorGameUrlFailed { url, error } => match language {
+        En => format!("game {url} is not usable: {error}"),
+        ZhCn => format!("游戏 {url} 不可用：{error}"),
+        Ru => format!("игра {url} недоступна: {error}"),
+      },
+      Msg::DoctorGameFailed { error } => match language {
+        En => format!("game is not usable: {error}"),
+        ZhCn => format!("游戏不可用：{error}"),
+        Ru => format!("игра недоступна: {error}"),
+      },
+      Msg::DoctorWritable { path } => {
+        let path = path.display();
+        match language {
+          En => format!("{path} is writable"),
+          ZhCn => format!("{path} 可写"),
+          Ru => format!("{path} доступен для записи"),
+        }
+      }
+      Msg::DoctorNotWritable { path, error } => {
+        let path = path.display();
+        match language {
+          En => format!("{path} is not writable: {error}"),
+          ZhCn => format!("{path} 不可写：{error}"),
+          Ru => format!("{path} недоступен для записи: {error}"),
+        }
+      }
+      Msg::DoctorInstalledRecords { mods } => match language {
+        En => format!("{mods} installed mods recorded"),
+        ZhCn => format!("已记录 {mods} 个已安装的模组"),
+        Ru => format!("записано установленных модов: {mods}"),
+      },
+      Msg::DoctorCorruptStore { path, error } => {
+        let path = path.display();
+        match language {
+          En => format!("{path} cannot be read: {error}"),
+          ZhCn => format!("无法读取 {path}：{error}"),
+          Ru => format!("не удаётся прочитать {path}: {error}"),
+        }
+      }
+      Msg::DoctorCacheRecords { resolver, entries } => match language {
+        En => format!("{resolver} cache has {entries} entries"),
+        ZhCn => format!("{resolver} 缓存有 {entries} 个条目"),
+        Ru => format!("записей в кэше {resolver}: {entries}"),
+      },
+      Msg::DoctorCacheRecordsFailed { resolver, error } => match language {
+        En => format!("{resolver} cache records cannot be read: {error}"),
+        ZhCn => format!("无法读取 {resolver} 缓存记录：{error}"),
+        Ru => format!("не удаётся прочитать записи кэша {resolver}: {error}"),
+      },
+      Msg::DoctorMissingCache { resolver, entries } => match language {
+        En => format!("{entries} {resolver} cache entries have no files"),
+        ZhCn => format!("{entries} 个 {resolver} 缓存条目缺少文件"),
+        Ru => format!("записей кэша {resolver} без файлов: {entries}"),
+      },
+      Msg::DoctorNoLeftovers => pick(
+        language,
+        "no leftover partial downloads",
+        "没有残留的未完成下载",
+        "нет оставшихся незавершённых загрузок",
+      ),
+      Msg::DoctorLeftovers { files, size } => match language {
+        En => format!("{files} leftover partial downloads take {size}"),
+        ZhCn => format!("{files} 个残留的未完成下载占用 {size}"),
+        Ru => format!("оставшиеся незавершённые загрузки ({files}) занимают {size}"),
+      },
+      Msg::DoctorOffline => pick(
+        language,
+        "offline, network checks skipped",
+        "离线模式，跳过网络检查",
+        "автономный режим, проверки сети пропущены",
+      ),
+      Msg::DoctorStationReachable { url } => match language {
+        En => format!("station {url} is reachable"),
+        ZhCn => format!("可以访问站点 {url}"),
+        Ru => format!("станция {url} доступна"),
+      },
+      Msg::DoctorStationUnreachable { url, error } => match language {
+        En => format!("station {url} is unreachable: {error}"),
+        ZhCn => format!("无法访问站点 {url}：{error}"),
+        Ru => format!("станция {url} недоступна: {error}"),
+      },
+      Msg::DoctorClockOk => pick(
+        language,
+        "system clock matches the station",
+        "系统时钟与站点一致",
+        "системные часы совпадают со станцией",
+      ),
+      Msg::DoctorClockSkew { seconds } => match language {
+        En => format!("system clock is {seconds}s off the station"),
+        ZhCn => format!("系统时钟与站点相差 {seconds} 秒"),
+        Ru => format!("системные часы расходятся со станцией на {seconds} с"),
+      },
+
+      Msg::GameNotSpecified => pick(
+        language,
+        "game not specified",
+        "未指定游戏",
+        "игра не указана",
+      ),
+      Msg::GameNotFound { name } => match language {
+        En => format!("game not found: {name}"),
+        ZhCn => format!("未找到游戏：{name}"),
+        Ru => format!("игра не найдена: {name}"),
+      },
+      Msg::GameExists { name } => match language {
+        En => format!("game already exists: {name}"),
+        ZhCn => format!("游戏已存在：{name}"),
+        Ru => format!("игра уже существует: {name}"),
+      },
+      Msg::GameNotDetected => pick(
+        language,
+        "no game detected",
+        "未找到游戏",
+        "игры не найдены",
+      ),
+      Msg::InvalidFileUrl { url } => match language {
+        En => format!("not a local file url: {url}"),
+        ZhCn => format!("不是本地文件地址：{url}"),
+        Ru => format!("не адрес локального файла: {url}"),
+      },
+      Msg::InvalidWineUrl { url } => match language {
+        En => format!("invalid wine game url: {url}"),
+        ZhCn => format!("无效的 wine 游戏地址：{url}"),
+        Ru => format!("неверный адрес игры wine: {url}"),
+      },
+      Msg::InvalidWindowsPath { path } => match language {
+        En => format!("not a windows path: {path}"),
+        ZhCn => format!("不是 Windows 路径：{path}"),
+        Ru => format!("не путь Windows: {path}"),
+      },
+      Msg::DriveNotMapped { drive, prefix } => {
+        let prefix = prefix.display();
+        match language {
+          En => format!("drive {drive} is not mapped in wine prefix {prefix}"),
+          ZhCn => format!("wine 前缀 {prefix} 中没有映射盘符 {drive}"),
+          Ru => format!("диск {drive} не подключён в префиксе wine {prefix}"),
+        }
+      }
+      Msg::AmbiguousGame { game_roots } => {
+        let game_roots = game_roots
+          .iter()
+          .map(|x| x.display().to_string())
+          .collect::<Vec<_>>()
+          .join(", ");
+        match language {
+          En => format!("several games detected: {game_roots}"),
+          ZhCn => format!("找到多个游戏：{game_roots}"),
+          Ru => format!("найдено несколько игр: {game_roots}"),
+        }
+      }
+      Msg::InvalidDefaultGame { value } => match language {
+        En => format!("invalid config 'default_game' {value:?}"),
+        ZhCn => format!("配置 'default_game' 无效：{value:?}"),
+        Ru => format!("неверное значение 'default_game' в конфигурации: {value:?}"),
+      },
+      Msg::InvalidDefaultGameReason { value, reason } => match language {
+        En => format!("invalid config 'default_game' {value:?}: {reason}"),
+        ZhCn => format!("配置 'default_game' 无效：{value:?}：{reason}"),
+        Ru => format!("неверное значение 'default_game' в конфигурации: {value:?}: {reason}"),
+      },
+      Msg::UnsupportedGameScheme { scheme } => match language {
+        En => format!("unsupported game url scheme: {scheme}"),
+        ZhCn => format!("不支持的游戏地址协议：{scheme}"),
+        Ru => format!("неподдерживаемая схема адреса игры: {scheme}"),
+      },
+      Msg::VersionNotFound { version } => match language {
+        En => format!("version not found: {version}"),
+        ZhCn => format!("未找到版本：{version}"),
+        Ru => format!("версия не найдена: {version}"),
+      },
+      Msg::VersionsNotSupported { resolver, url } => match language {
+        En => format!("{resolver} resolver cannot pick a version of the mod: {url}"),
+        ZhCn => format!("{resolver} 解析器不能指定模组版本：{url}"),
+        Ru => format!("резолвер {resolver} не умеет выбирать версию мода: {url}"),
+      },
+      Msg::NoGameVersion { game_root } => {
+        let game_root = game_root.display();
+        match language {
+          En => format!("no game version found in {game_root}"),
+          ZhCn => format!("{game_root} 中没有游戏版本"),
+          Ru => format!("в {game_root} не найдено ни одной версии игры"),
+        }
+      }
+      Msg::GameDirIsNotADir => pick(
+        language,
+        "game dir is not a dir",
+        "游戏目录不是目录",
+        "каталог игры не является каталогом",
+      ),
+      Msg::IllegalGameDirStructure => pick(
+        language,
+        "illegal game dir structure",
+        "游戏目录结构不正确",
+        "неверная структура каталога игры",
+      ),
+      Msg::ModNotFound => pick(language, "mod not found", "未找到模组", "мод не найден"),
+      Msg::ResolverNotFound { resolver } => match language {
+        En => format!("resolver not found: {resolver}"),
+        ZhCn => format!("未找到解析器：{resolver}"),
+        Ru => format!("резолвер не найден: {resolver}"),
+      },
+      Msg::CacheEntryNotFound { entry } => match language {
+        En => format!("cache entry not found: {entry}"),
+        ZhCn => format!("未找到缓存条目：{entry}"),
+        Ru => format!("запись кэша не найдена: {entry}"),
+      },
+      Msg::ModNotInstalled { url, game_root } => {
+        let game_root = game_root.display();
+        match language {
+          En => format!("mod not installed in {game_root}: {url}"),
+          ZhCn => format!("模组未安装在 {game_root} 中：{url}"),
+          Ru => format!("мод не установлен в {game_root}: {url}"),
+        }
+      }
+      Msg::Locked { path } => {
+        let path = path.display();
+        match language {
+          En => format!("another kmf is running (lock held on {path})"),
+          ZhCn => format!("另一个 kmf 正在运行（锁文件 {path}）"),
+          Ru => format!("уже запущен другой kmf (заблокирован {path})"),
+        }
+      }
+      Msg::CannotResolve => pick(
+        language,
+        "no resolver can resolve the url",
+        "没有解析器能解析该地址",
+        "ни один резолвер не может обработать адрес",
+      ),
+      Msg::NotAvailableOffline { url } => match language {
+        En => format!("{url} is not cached and cannot be downloaded offline"),
+        ZhCn => format!("{url} 未缓存，离线模式下无法下载"),
+        Ru => format!("{url} нет в кэше, и его нельзя скачать в автономном режиме"),
+      },
+      Msg::DigestMismatch {
+        url,
+        expected,
+        actual,
+      } => match language {
+        En => format!("digest mismatch for {url}: expected {expected}, got {actual}"),
+        ZhCn => format!("{url} 的摘要不匹配：应为 {expected}，实际为 {actual}"),
+        Ru => {
+          format!("контрольная сумма {url} не совпадает: ожидалась {expected}, получена {actual}")
+        }
+      },
+
+      Msg::ConfigParse {
+        path,
+        line,
+        column,
+        message,
+      } => {
+        let path = path.display();
+        match language {
+          En => format!("invalid config {path}:{line}:{column}: {message}"),
+          ZhCn => format!("配置无效 {path}:{line}:{column}：{message}"),
+          Ru => format!("неверная конфигурация {path}:{line}:{column}: {message}"),
+        }
+      }
+      Msg::NotWritable { key, path } => {
+        let path = path.display();
+        match language {
+          En => format!("'{key}' {path} is not writable"),
+          ZhCn => format!("'{key}' {path} 不可写"),
+          Ru => format!("'{key}' {path} недоступен для записи"),
+        }
+      }
+      Msg::InvalidConfigValue { key, message } => match language {
+        En => format!("invalid value for '{key}': {message}"),
+        ZhCn => format!("'{key}' 的值无效：{message}"),
+        Ru => format!("неверное значение '{key}': {message}"),
+      },
+      Msg::ConfigExists { path } => {
+        let path = path.display();
+        match language {
+          En => format!("config file already exists: {path}"),
+          ZhCn => format!("配置文件已存在：{path}"),
+          Ru => format!("файл конфигурации уже существует: {path}"),
+        }
+      }
+      Msg::ChecksFailed { failed } => match language {
+        En => format!("{failed} doctor checks failed"),
+        ZhCn => format!("{failed} 项检查未通过"),
+        Ru => format!("не пройдено проверок: {failed}"),
+      },
+      Msg::Drifted { mods } => match language {
+        En => format!("{mods} installed mods differ from their sources"),
+        ZhCn => format!("{mods} 个已安装的模组与来源不一致"),
+        Ru => format!("установленные моды расходятся с источниками: {mods}"),
+      },
+
+      Msg::HintConfig => pick(
+        language,
+        "run `kmf config check` to validate the config",
+        "运行 `kmf config check` 检查配置",
+        "выполните `kmf config check`, чтобы проверить конфигурацию",
+      ),
+      Msg::HintGameNotSpecified => pick(
+        language,
+        "pass `--game file:///path/to/game` or a game name, or set `default_game` in config",
+        "请传入 `--game file:///path/to/game` 或游戏名，或在配置中设置 `default_game`",
+        "передайте `--game file:///path/to/game` или имя игры, или задайте `default_game`",
+      ),
+      Msg::HintGameList => pick(
+        language,
+        "run `kmf game list` to see the games, or `kmf game add` to add one",
+        "运行 `kmf game list` 查看游戏，或用 `kmf game add` 添加",
+        "выполните `kmf game list`, чтобы увидеть игры, или `kmf game add`, чтобы добавить",
+      ),
+      Msg::HintInvalidDefaultGame => pick(
+        language,
+        "`default_game` should be an url like `file:///path/to/game` or the name of a game in `[games]`",
+        "`default_game` 应为类似 `file:///path/to/game` 的地址或 `[games]` 中的游戏名",
+        "`default_game` должен быть адресом вида `file:///path/to/game` или именем игры из `[games]`",
+      ),
+      Msg::HintUnsupportedGameScheme => pick(
+        language,
+        "only `file`, `detect` and `wine` game urls are supported",
+        "只支持 `file`、`detect` 和 `wine` 协议的游戏地址",
+        "поддерживаются только адреса игры со схемами `file`, `detect` и `wine`",
+      ),
+      Msg::HintFileUrl => pick(
+        language,
+        "use an url like `file:///path/to/game`, with spaces written as `%20`",
+        "请使用类似 `file:///path/to/game` 的地址，空格写作 `%20`",
+        "используйте адрес вида `file:///path/to/game`, пробелы записываются как `%20`",
+      ),
+      Msg::HintWineUrl => pick(
+        language,
+        "use an url like `wine:///home/u/.wine?path=C:/Games/Korabli`",
+        "请使用类似 `wine:///home/u/.wine?path=C:/Games/Korabli` 的地址",
+        "используйте адрес вида `wine:///home/u/.wine?path=C:/Games/Korabli`",
+      ),
+      Msg::HintDosDevices => pick(
+        language,
+        "check the drive links in `dosdevices` of the prefix, e.g. with `winecfg`",
+        "请检查前缀 `dosdevices` 中的盘符链接，例如使用 `winecfg`",
+        "проверьте ссылки дисков в `dosdevices` префикса, например через `winecfg`",
+      ),
+      Msg::HintGameNotDetected => pick(
+        language,
+        "add the game with `kmf game add <name> <path>`",
+        "请用 `kmf game add <name> <path>` 添加游戏",
+        "добавьте игру через `kmf game add <name> <path>`",
+      ),
+      Msg::HintAmbiguousGame => pick(
+        language,
+        "pick one with `detect://steam`, `proton`, `lutris` or `wine`, or add it with `kmf game add`",
+        "用 `detect://steam`、`proton`、`lutris` 或 `wine` 选择其一，或用 `kmf game add` 添加",
+        "выберите одну через `detect://steam`, `proton`, `lutris` или `wine`, или добавьте через `kmf game add`",
+      ),
+      Msg::HintGameDirStructure => pick(
+        language,
+        "the game dir should contain `bin/<build number>/`",
+        "游戏目录中应包含 `bin/<构建号>/`",
+        "каталог игры должен содержать `bin/<номер сборки>/`",
+      ),
+      Msg::HintVersionNotFound => pick(
+        language,
+        "omit `version` in the game url to use the newest one",
+        "去掉游戏地址中的 `version` 以使用最新版本",
+        "уберите `version` из адреса игры, чтобы использовать новейшую версию",
+      ),
+      Msg::HintResolverNotFound => pick(
+        language,
+        "available resolvers: `kmf`, `web`",
+        "可用的解析器：`kmf`、`web`",
+        "доступные резолверы: `kmf`, `web`",
+      ),
+      Msg::HintCacheEntryNotFound => pick(
+        language,
+        "run `kmf cache list` to see cached mods",
+        "运行 `kmf cache list` 查看已缓存的模组",
+        "выполните `kmf cache list`, чтобы увидеть кэшированные моды",
+      ),
+      Msg::HintWait => pick(
+        language,
+        "pass `--wait` to wait for it",
+        "加上 `--wait` 以等待其完成",
+        "добавьте `--wait`, чтобы дождаться его завершения",
+      ),
+      Msg::HintCannotResolve => pick(
+        language,
+        "mod urls should use `kmf`, `http` or `https` scheme",
+        "模组地址应使用 `kmf`、`http` 或 `https` 协议",
+        "адреса модов должны использовать схему `kmf`, `http` или `https`",
+      ),
+      Msg::HintOffline => pick(
+        language,
+        "run without `--offline` to download it",
+        "去掉 `--offline` 以下载",
+        "запустите без `--offline`, чтобы скачать его",
+      ),
+      Msg::HintCorruptDownload => pick(
+        language,
+        "the download is corrupt, try again",
+        "下载的文件已损坏，请重试",
+        "загруженный файл повреждён, попробуйте ещё раз",
+      ),
+      Msg::HintInvalidZip => pick(
+        language,
+        "the mod is not a valid zip archive",
+        "模组不是有效的 zip 压缩包",
+        "мод не является корректным zip-архивом",
+      ),
+      Msg::HintUrlNotFound => pick(
+        language,
+        "check the mod url, the server has nothing there",
+        "请检查模组地址，服务器上没有该文件",
+        "проверьте адрес мода, на сервере его нет",
+      ),
+      Msg::HintNetwork => pick(
+        language,
+        "check your network connection, or use `--offline`",
+        "请检查网络连接，或使用 `--offline`",
+        "проверьте подключение к сети или используйте `--offline`",
+      ),
+      Msg::HintNotWritable => pick(
+        language,
+        "check its permissions, or point it to another dir with `kmf config set`",
+        "请检查其权限，或用 `kmf config set` 改为其他目录",
+        "проверьте права доступа или укажите другой каталог через `kmf config set`",
+      ),
+      Msg::HintForce => pick(
+        language,
+        "pass `--force` to overwrite it",
+        "加上 `--force` 以覆盖",
+        "добавьте `--force`, чтобы перезаписать его",
+      ),
+      Msg::HintRepair => pick(
+        language,
+        "run `kmf verify --repair` to restore the files",
+        "运行 `kmf verify --repair` 以恢复文件",
+        "выполните `kmf verify --repair`, чтобы восстановить файлы",
+      ),
+      Msg::HintGamePermissions => pick(
+        language,
+        "make the game dir writable by your user, or move the game out of a protected dir",
+        "请让当前用户可以写入游戏目录，或将游戏移出受保护的目录",
+        "сделайте каталог игры доступным для записи или перенесите игру из защищённого каталога",
+      ),
+      Msg::HintCorruptStore { path } => {
+        let path = path.display();
+        match language {
+          En => format!("move {path} away, kmf starts with an empty one"),
+          ZhCn => format!("移走 {path}，kmf 会重新创建"),
+          Ru => format!("переместите {path}, kmf создаст новый"),
+        }
+      }
+      Msg::HintClearCache { resolver } => match language {
+        En => format!("run `kmf cache clear --resolver {resolver}`"),
+        ZhCn => format!("运行 `kmf cache clear --resolver {resolver}`"),
+        Ru => format!("выполните `kmf cache clear --resolver {resolver}`"),
+      },
+      Msg::HintLeftovers { dir } => {
+        let dir = dir.display();
+        match language {
+          En => format!("they are interrupted downloads, delete `*/partial/*` under {dir}"),
+          ZhCn => format!("这些是中断的下载，可删除 {dir} 下的 `*/partial/*`"),
+          Ru => format!("это прерванные загрузки, удалите `*/partial/*` в {dir}"),
+        }
+      }
+      Msg::HintClock => pick(
+        language,
+        "sync the system clock, cache freshness is judged by server times",
+        "请同步系统时钟，缓存是否过期按服务器时间判断",
+        "синхронизируйте системные часы, свежесть кэша оценивается по времени сервера",
+      ),
+    }
+  }
+}
+
+impl fmt::Display for Msg<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.localize(language()).as_str())
+  }
+}
@@ -1,131 +1,1052 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+  collections::{BTreeMap, HashSet},
+  path::{Path, PathBuf},
+};
 
 use crate::{
-  config::Config,
-  resolver::{
-    self,
-    impls::{kmf::KmfResolver, web::WebResolver},
-  },
-  task::Task,
-  util::{async_copy_dir, ensure_dir, get_game_versions},
+  config::{Config, GameConfig, OutputFormat},
+  event::{Event, EventReporter, Warning},
+  i18n::Msg,
+  resolver::{self, CacheEntry},
+  task::{DryRun, Task},
+  util::{ensure_dir, format_size, list_files, lock::FileLock},
 };
-use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+use chrono::{DateTime, Utc};
+use reqwest_middleware::ClientWithMiddleware;
+use sha2::{Digest, Sha256};
+use tokio::fs;
 
-mod error;
+mod builder;
+mod detect;
+mod doctor;
+pub mod error;
+mod game;
+mod installed;
+mod plan;
+mod verify;
+mod wine;
 
+pub use builder::KmfBuilder;
+pub use detect::{DetectedGame, GameSource, detect_games};
 use error::Error;
+pub use game::{GameBuild, GameInstall, GameRef, is_game_name};
+use installed::{InstalledMod, InstalledMods};
+use plan::Plan;
+use tracing::debug;
 use url::Url;
+use verify::{Drift, changed_sources, hash_files};
+pub(crate) use wine::{is_windows_path, unix_path};
+
+/// Supported game url schemes
+pub const GAME_SCHEMES: &[&str] = &["file", "detect", "wine"];
 
+/// Mod manager, build it with [`KmfBuilder`] or [`Kmf::try_from_config`]
 pub struct Kmf {
-  default_game: Option<Url>,
-  multi_progress: MultiProgress,
+  default_game: Option<GameRef>,
+  /// Named games of config
+  games: BTreeMap<String, GameConfig>,
+  cache_lock_file: PathBuf,
+  /// Wait for other kmf processes instead of failing
+  wait_for_lock: bool,
+  installed_mods: InstalledMods,
+  cache_dir: PathBuf,
+  data_dir: PathBuf,
+  /// Http client of the built-in resolvers
+  http_client: ClientWithMiddleware,
+  http_cache_dir: PathBuf,
+  cache_size_limit: Option<u64>,
+  /// Only use resolvers able to work offline
+  offline: bool,
+  output: OutputFormat,
+  reporter: EventReporter,
   resolvers: Vec<Box<dyn resolver::Resolver>>,
 }
 
 impl Kmf {
-  /// Use config construct the Kmf instance
+  /// Use config construct the Kmf instance, see [`KmfBuilder`] for more options
   pub async fn try_from_config(config: &Config) -> Result<Self, Error> {
-    let default_game = config
-      .default_game
-      .as_ref()
-      .map(|x| x.parse().expect("invalid config 'default_game'"));
-    let cache_dir = ensure_dir(config.cache_dir.as_path()).await?;
-
-    let multi_progress = MultiProgress::with_draw_target(match config.progress_draw_target {
-      crate::config::ProgressDrawTargetType::Stdout => ProgressDrawTarget::stdout(),
-      crate::config::ProgressDrawTargetType::Hidden => ProgressDrawTarget::hidden(),
+    KmfBuilder::new(config.to_owned()).build().await
+  }
+
+  /// Resolver with the highest priority able to resolve `url`
+  fn find_resolver(&self, url: &Url) -> Result<&dyn resolver::Resolver, Error> {
+    let resolver = self
+      .resolvers
+      .iter()
+      .map(AsRef::as_ref)
+      .filter(|r| !self.offline || r.capabilities().offline)
+      .find(|r| r.can_resolve(url))
+      .ok_or(Error::ModNotFound)?;
+    if resolver::picks_version(url) && !resolver.capabilities().versions {
+      return Err(Error::VersionsNotSupported {
+        resolver: resolver.name().to_string(),
+        url: Box::new(url.to_owned()),
+      });
+    }
+    Ok(resolver)
+  }
+
+  /// Cache the mod and return the cached dir, only look into cache when not `download`
+  async fn cache_mod(&self, url: &Url, download: bool) -> Result<Option<PathBuf>, Error> {
+    let resolver = self.find_resolver(url)?;
+    if !download {
+      let id = resolver.cache_id(url)?;
+      return Ok(
+        resolver
+          .cache_entries()
+          .await?
+          .into_iter()
+          .find(|x| x.id == id)
+          .map(|x| x.path),
+      );
+    }
+    let artifact = resolver.resolve(url).await?;
+    self.reporter.report(Event::Resolved {
+      url: url.to_owned(),
+      id: artifact.id.to_owned(),
+      version: artifact.version.to_owned(),
+      size: artifact.size,
     });
+    Ok(Some(resolver.fetch(&artifact).await?))
+  }
+
+  async fn lock_cache(&self) -> Result<FileLock, Error> {
+    Ok(FileLock::acquire(self.cache_lock_file.to_owned(), self.wait_for_lock).await?)
+  }
+
+  /// Lock the game, the lock file lives in `data_dir/locks` named by the hash of the real
+  /// game root so nothing is written into the game
+  async fn lock_game(&self, game_root: &Path) -> Result<FileLock, Error> {
+    let game_root = fs::canonicalize(game_root)
+      .await
+      .unwrap_or_else(|_| game_root.to_path_buf());
+    let hash = hex::encode(Sha256::digest(game_root.as_os_str().as_encoded_bytes()));
+    let lock_dir = self.data_dir.join("locks");
+    Ok(
+      FileLock::acquire(
+        ensure_dir(lock_dir.as_path())
+          .await?
+          .join(format!("{}.lock", &hash[..16])),
+        self.wait_for_lock,
+      )
+      .await?,
+    )
+  }
+
+  fn warn(&self, warning: Warning) {
+    self.reporter.report(Event::warning(warning));
+  }
+
+  /// Canonical root of the game, so records match however the game is reached
+  async fn game_root(game: &Url) -> Result<PathBuf, Error> {
+    let game_root = Self::game_root_uncanonicalized(game).await?;
+    // 不存在的目录留给读取游戏版本时报错
+    Ok(
+      fs::canonicalize(game_root.as_path())
+        .await
+        .unwrap_or(game_root),
+    )
+  }
 
-    Ok(Self {
-      default_game,
-      multi_progress,
-      resolvers: vec![
-        Box::new(KmfResolver::new(cache_dir.join("kmf_resolver")).await?),
-        Box::new(WebResolver::new(cache_dir.join("web_resolver")).await?),
-      ],
+  async fn game_root_uncanonicalized(game: &Url) -> Result<PathBuf, Error> {
+    match game.scheme() {
+      "file" => game.to_file_path().map_err(|()| Error::InvalidFileUrl {
+        url: Box::new(game.to_owned()),
+      }),
+      // `wine:///<前缀>?path=C:/Games/Korabli`
+      "wine" => {
+        let invalid = || Error::InvalidWineUrl {
+          url: Box::new(game.to_owned()),
+        };
+        let prefix = game.to_file_path().map_err(|()| invalid())?;
+        let path = game
+          .query_pairs()
+          .find_map(|(k, v)| (k == "path").then_some(v))
+          .ok_or_else(invalid)?;
+        wine::unix_path(prefix.as_path(), path.as_ref()).await
+      }
+      // `detect://<source>` 只考虑该来源找到的游戏
+      "detect" => {
+        let mut games = detect_games().await;
+        if let Some(source) = game.host_str() {
+          games.retain(|x| x.source.name() == source);
+        }
+        match games.as_slice() {
+          [] => Err(Error::GameNotDetected),
+          [game] => Ok(game.install.game_root.to_owned()),
+          games => Err(Error::AmbiguousGame {
+            game_roots: games
+              .iter()
+              .map(|x| x.install.game_root.to_owned())
+              .collect(),
+          }),
+        }
+      }
+      scheme => Err(Error::UnsupportedGameScheme {
+        scheme: scheme.to_string(),
+      }),
+    }
+  }
+
+  /// Version given by `version` in the game url
+  fn game_version_query(game: &Url) -> Option<String> {
+    game.query_pairs().find_map(|(k, v)| {
+      if k == "version" {
+        Some(v.to_string())
+      } else {
+        None
+      }
     })
   }
 
-  async fn cache_mod(&self, url: &Url) -> Result<(PathBuf, String), Error> {
-    let Some(resolver) = self
-      .resolvers
+  /// Build given in the game url by its number or client version, or the newest one
+  async fn game_version(game: &Url, game_root: &Path) -> Result<String, Error> {
+    let install = GameInstall::read(game_root).await?;
+    if let Some(version) = Self::game_version_query(game) {
+      Ok(
+        install
+          .find_build(version.as_str())
+          .ok_or(Error::VersionNotFound { version })?
+          .build
+          .to_owned(),
+      )
+    } else {
+      Ok(
+        install
+          .builds
+          .first()
+          .ok_or_else(|| Error::NoGameVersion {
+            game_root: game_root.to_path_buf(),
+          })?
+          .build
+          .to_owned(),
+      )
+    }
+  }
+
+  /// Build given in the game url, client versions are mapped to their build when it is
+  /// still in the game
+  async fn game_build_query(game: &Url, game_root: &Path) -> Option<String> {
+    let version = Self::game_version_query(game)?;
+    let build = GameInstall::read(game_root)
+      .await
+      .ok()
+      .and_then(|x| Some(x.find_build(version.as_str())?.build.to_owned()));
+    Some(build.unwrap_or(version))
+  }
+
+  fn res_mods_root(game_root: &Path, version: &str) -> PathBuf {
+    game_root.join("bin").join(version).join("res_mods")
+  }
+
+  fn report_plan(&self, task: &str, installed_mod: &InstalledMod, plan: Plan) {
+    if self.output == OutputFormat::Human {
+      println!(
+        "{}",
+        Msg::PlanHeader {
+          task,
+          url: &installed_mod.url,
+          game_root: installed_mod.game_root.as_path(),
+          version: installed_mod.version.as_str(),
+        }
+      );
+      if plan.is_empty() {
+        println!("  {}", Msg::NothingToDo);
+      }
+      for (mark, files) in [
+        ("+", &plan.create),
+        ("~", &plan.overwrite),
+        ("-", &plan.remove),
+      ] {
+        for file in files {
+          println!("  {mark} {}", file.display());
+        }
+      }
+      for conflict in plan.conflicts.iter() {
+        println!(
+          "  ! {}",
+          Msg::AlsoInstalledBy {
+            path: conflict.path.as_path(),
+            url: &conflict.url,
+          }
+        );
+      }
+    }
+    self.reporter.report(Event::Plan {
+      task: task.to_string(),
+      url: installed_mod.url.to_owned(),
+      game_root: installed_mod.game_root.to_owned(),
+      version: installed_mod.version.to_owned(),
+      create: plan.create,
+      overwrite: plan.overwrite,
+      remove: plan.remove,
+      conflicts: plan.conflicts,
+    });
+  }
+
+  /// Install the mod into the game version, replacing files installed before by the same mod
+  async fn install_mod(
+    &self,
+    task: &str,
+    url: &Url,
+    game_root: &Path,
+    version: &str,
+    dry_run: Option<DryRun>,
+  ) -> Result<(), Error> {
+    let mod_cache_root = self
+      .cache_mod(url, dry_run.is_none_or(|x| x.download))
+      .await?;
+    let Some(mod_cache_root) = mod_cache_root else {
+      self.warn(Warning::NotInCache {
+        url: url.to_owned(),
+      });
+      return Ok(());
+    };
+    // 试运行不下载时缓存可能已过时，至少检查一下是否有更新
+    if dry_run.is_some_and(|x| !x.download) && !self.find_resolver(url)?.is_up_to_date(url).await? {
+      self.warn(Warning::UpdatePending {
+        url: url.to_owned(),
+      });
+    }
+
+    let _game_lock = match dry_run {
+      Some(_) => None,
+      None => Some(self.lock_game(game_root).await?),
+    };
+    let (old, others) = self
+      .installed_mods
+      .list()
+      .await?
+      .into_iter()
+      .filter(|x| x.game_root == game_root && x.version == version)
+      .partition::<Vec<_>, _>(|x| &x.url == url);
+    let res_mods_root = Self::res_mods_root(game_root, version);
+    let files = list_files(mod_cache_root.to_owned()).await?;
+    let old_files = old.into_iter().flat_map(|x| x.files).collect::<Vec<_>>();
+    let plan = Plan::install(
+      mod_cache_root.as_path(),
+      res_mods_root.as_path(),
+      files.as_slice(),
+      old_files.as_slice(),
+      others.as_slice(),
+    )
+    .await?;
+    let installed_mod = InstalledMod {
+      url: url.to_owned(),
+      game_root: game_root.to_path_buf(),
+      version: version.to_string(),
+      installed_at: Utc::now(),
+      files,
+      hashes: BTreeMap::new(),
+    };
+    if dry_run.is_some() {
+      self.report_plan(task, &installed_mod, plan);
+      return Ok(());
+    }
+    let mut installed_mod = installed_mod;
+    installed_mod.hashes = hash_files(mod_cache_root.as_path(), &installed_mod.files).await?;
+
+    plan
+      .apply(Some(mod_cache_root.as_path()), res_mods_root.as_path())
+      .await?;
+    self.installed_mods.insert(installed_mod.to_owned()).await?;
+    self.reporter.report(Event::Installed {
+      url: installed_mod.url,
+      game_root: installed_mod.game_root,
+      version: installed_mod.version,
+      files: installed_mod.files,
+    });
+
+    Ok(())
+  }
+
+  async fn task_install(
+    &self,
+    url: &Url,
+    game: &Url,
+    dry_run: Option<DryRun>,
+  ) -> Result<(), Error> {
+    // 安装完成前缓存不能被其他进程清理，始终先锁缓存再锁游戏
+    let _cache_lock = self.lock_cache().await?;
+    let game_root = Self::game_root(game).await?;
+    let version = Self::game_version(game, game_root.as_path()).await?;
+
+    self
+      .install_mod(
+        "install",
+        url,
+        game_root.as_path(),
+        version.as_str(),
+        dry_run,
+      )
+      .await
+  }
+
+  /// Reinstall mods of the game into its current version with their newest files
+  async fn task_update(
+    &self,
+    urls: &[Url],
+    game: &Url,
+    dry_run: Option<DryRun>,
+  ) -> Result<(), Error> {
+    let _cache_lock = self.lock_cache().await?;
+    let game_root = Self::game_root(game).await?;
+    let version = Self::game_version(game, game_root.as_path()).await?;
+    let mut installed_urls = self
+      .installed_mods
+      .list()
+      .await?
+      .into_iter()
+      .filter(|x| x.game_root == game_root)
+      .map(|x| x.url)
+      .collect::<Vec<_>>();
+    installed_urls.sort();
+    installed_urls.dedup();
+    for url in urls {
+      if !installed_urls.contains(url) {
+        return Err(Error::ModNotInstalled {
+          url: Box::new(url.to_owned()),
+          game_root,
+        });
+      }
+    }
+
+    for url in installed_urls
       .iter()
-      .find(|r| r.can_resolve(url.to_owned()))
-    else {
-      return Err(Error::ModNotFound);
+      .filter(|x| urls.is_empty() || urls.contains(x))
+    {
+      self
+        .install_mod(
+          "update",
+          url,
+          game_root.as_path(),
+          version.as_str(),
+          dry_run,
+        )
+        .await?;
+    }
+    Ok(())
+  }
+
+  async fn task_uninstall(
+    &self,
+    url: &Url,
+    game: &Url,
+    dry_run: Option<DryRun>,
+  ) -> Result<(), Error> {
+    let _cache_lock = self.lock_cache().await?;
+    let game_root = Self::game_root(game).await?;
+    let version = Self::game_build_query(game, game_root.as_path()).await;
+    let _game_lock = match dry_run {
+      Some(_) => None,
+      None => Some(self.lock_game(game_root.as_path()).await?),
     };
-    let resolve_info = resolver.resolve(url.to_owned()).await?;
-    let dir = resolver.cache(url.to_owned()).await?;
-    Ok((dir, resolve_info.id))
-  }
-
-  async fn task_install(&self, url: &Url, game: &Url) -> Result<(), Error> {
-    let pb = self.multi_progress.add(ProgressBar::new_spinner());
-    pb.enable_steady_tick(Duration::from_millis(100));
-    pb.set_message("缓存中");
-    let (mod_cache_root, _id) = self.cache_mod(url).await?;
-    pb.set_message("缓存完成");
-    pb.finish();
-
-    let pb = self.multi_progress.add(ProgressBar::new_spinner());
-    pb.enable_steady_tick(Duration::from_millis(100));
-    pb.set_message("检查游戏版本中");
-    let game_root = match game.scheme() {
-      "file" => game.path(),
-      _ => todo!(),
+    let installed_mods = self
+      .installed_mods
+      .list()
+      .await?
+      .into_iter()
+      .filter(|x| x.game_root == game_root)
+      .collect::<Vec<_>>();
+    let (targets, others) = installed_mods.into_iter().partition::<Vec<_>, _>(|x| {
+      &x.url == url && version.as_ref().is_none_or(|v| v == &x.version)
+    });
+    if targets.is_empty() {
+      return Err(Error::ModNotInstalled {
+        url: Box::new(url.to_owned()),
+        game_root,
+      });
+    }
+
+    for mut installed_mod in targets {
+      if installed_mod.files.is_empty() {
+        // 旧版本 kmf 没有记录安装的文件
+        match self.cache_mod(url, false).await? {
+          Some(mod_cache_root) => installed_mod.files = list_files(mod_cache_root).await?,
+          None => self.warn(Warning::UnknownFiles {
+            url: url.to_owned(),
+            game_root: installed_mod.game_root.to_owned(),
+          }),
+        }
+      }
+      let others = others
+        .iter()
+        .filter(|x| x.version == installed_mod.version)
+        .cloned()
+        .collect::<Vec<_>>();
+      let res_mods_root = Self::res_mods_root(game_root.as_path(), installed_mod.version.as_str());
+      let plan = Plan::uninstall(
+        res_mods_root.as_path(),
+        installed_mod.files.as_slice(),
+        others.as_slice(),
+      )
+      .await?;
+      if dry_run.is_some() {
+        self.report_plan("uninstall", &installed_mod, plan);
+        continue;
+      }
+
+      plan.apply(None, res_mods_root.as_path()).await?;
+      self.installed_mods.remove(&installed_mod).await?;
+      self.reporter.report(Event::Uninstalled {
+        url: installed_mod.url,
+        game_root: installed_mod.game_root,
+        version: installed_mod.version,
+        files: plan.remove,
+      });
+    }
+    Ok(())
+  }
+
+  /// Check files of installed mods, restoring modified and missing ones when `repair`
+  async fn task_verify(&self, urls: &[Url], game: &Url, repair: bool) -> Result<(), Error> {
+    let _cache_lock = self.lock_cache().await?;
+    let game_root = Self::game_root(game).await?;
+    let version = Self::game_build_query(game, game_root.as_path()).await;
+    let _game_lock = if repair {
+      Some(self.lock_game(game_root.as_path()).await?)
+    } else {
+      None
     };
-    let game_root = PathBuf::from(game_root);
-    let game_root = game_root.as_path();
-    let version = {
-      let version = game
-        .query_pairs()
-        .find_map(|(k, v)| if k == "version" { Some(v) } else { None });
-      let versions = get_game_versions(game_root).await?;
-      if let Some(version) = version {
-        if versions
-          .iter()
-          .any(|x| x.as_str() == version.to_string().as_str())
-        {
-          version.to_string()
+    let installed_mods = self
+      .installed_mods
+      .list()
+      .await?
+      .into_iter()
+      .filter(|x| x.game_root == game_root)
+      .collect::<Vec<_>>();
+    for url in urls {
+      if !installed_mods.iter().any(|x| &x.url == url) {
+        return Err(Error::ModNotInstalled {
+          url: Box::new(url.to_owned()),
+          game_root,
+        });
+      }
+    }
+
+    let mut drifted = 0;
+    for installed_mod in installed_mods.iter().filter(|x| {
+      (urls.is_empty() || urls.contains(&x.url)) && version.as_ref().is_none_or(|v| v == &x.version)
+    }) {
+      let res_mods_root = Self::res_mods_root(game_root.as_path(), installed_mod.version.as_str());
+      let owned = installed_mods
+        .iter()
+        .filter(|x| x.version == installed_mod.version)
+        .flat_map(|x| x.files.iter().cloned())
+        .collect::<HashSet<_>>();
+      // 旧版本 kmf 没有记录文件哈希，与缓存比较
+      let cache = if installed_mod.hashes.is_empty() {
+        self.cache_mod(&installed_mod.url, false).await?
+      } else {
+        None
+      };
+      let drift = Drift::check(
+        res_mods_root.as_path(),
+        installed_mod,
+        cache.as_deref(),
+        &owned,
+      )
+      .await?;
+
+      let broken = drift.modified.len() + drift.missing.len();
+      let mut repaired = false;
+      if repair && broken > 0 {
+        let mod_cache_root = match self.cache_mod(&installed_mod.url, false).await? {
+          Some(mod_cache_root) => mod_cache_root,
+          None => self
+            .cache_mod(&installed_mod.url, true)
+            .await?
+            .expect("mod is downloaded"),
+        };
+        let plan = Plan {
+          create: drift.missing.to_owned(),
+          overwrite: drift.modified.to_owned(),
+          ..Default::default()
+        };
+        // 缓存中可能已是更新后的版本，不能用来恢复安装时的文件
+        let changed = changed_sources(
+          mod_cache_root.as_path(),
+          installed_mod,
+          [plan.create.as_slice(), plan.overwrite.as_slice()]
+            .concat()
+            .as_slice(),
+        )
+        .await?;
+        if changed.is_empty() {
+          plan
+            .apply(Some(mod_cache_root.as_path()), res_mods_root.as_path())
+            .await?;
+          repaired = true;
         } else {
-          return Err(Error::VersionNotFound {
-            version: version.to_string(),
+          self.warn(Warning::RepairSourceChanged {
+            url: installed_mod.url.to_owned(),
+            files: changed,
           });
         }
-      } else {
-        versions[0].to_owned()
       }
+      if broken > 0 && !repaired {
+        drifted += 1;
+      }
+
+      if self.output == OutputFormat::Human {
+        println!(
+          "{}",
+          Msg::PlanHeader {
+            task: "verify",
+            url: &installed_mod.url,
+            game_root: game_root.as_path(),
+            version: installed_mod.version.as_str(),
+          }
+        );
+        if drift.is_empty() {
+          println!("  {}", Msg::FilesIntact);
+        }
+        for (mark, files) in [
+          ("~", &drift.modified),
+          ("-", &drift.missing),
+          ("?", &drift.unexpected),
+          ("!", &drift.unknown),
+        ] {
+          for file in files {
+            println!("  {mark} {}", file.display());
+          }
+        }
+        if repaired {
+          println!("  {}", Msg::Repaired { files: broken });
+        }
+      }
+      self.reporter.report(Event::Verified {
+        url: installed_mod.url.to_owned(),
+        game_root: game_root.to_owned(),
+        version: installed_mod.version.to_owned(),
+        modified: drift.modified,
+        missing: drift.missing,
+        unexpected: drift.unexpected,
+        unknown: drift.unknown,
+        repaired,
+      });
+    }
+    if drifted > 0 {
+      return Err(Error::Drifted { mods: drifted });
+    }
+    Ok(())
+  }
+
+  fn select_resolvers(
+    &self,
+    resolver: Option<&str>,
+  ) -> Result<Vec<&dyn resolver::Resolver>, Error> {
+    let resolvers = self
+      .resolvers
+      .iter()
+      .map(AsRef::as_ref)
+      .filter(|r| resolver.is_none_or(|x| x == r.name()))
+      .collect::<Vec<_>>();
+    if resolvers.is_empty() {
+      return Err(Error::ResolverNotFound {
+        resolver: resolver.unwrap_or_default().to_string(),
+      });
+    }
+    Ok(resolvers)
+  }
+
+  /// Find cache entries by mod url, or by cache id (prefix)
+  async fn find_cache_entries<'a>(
+    &self,
+    resolvers: &[&'a dyn resolver::Resolver],
+    entry: &str,
+  ) -> Result<Vec<(&'a dyn resolver::Resolver, CacheEntry)>, Error> {
+    let url = entry.parse::<Url>().ok();
+    let mut found = Vec::new();
+    for resolver in resolvers {
+      let id = match url.as_ref() {
+        Some(url) if resolver.can_resolve(url) => Some(resolver.cache_id(url)?),
+        Some(_) => continue,
+        None => None,
+      };
+      for cache_entry in resolver.cache_entries().await? {
+        let matched = match id.as_ref() {
+          Some(id) => &cache_entry.id == id,
+          None => cache_entry.id.starts_with(entry),
+        };
+        if matched {
+          found.push((*resolver, cache_entry));
+        }
+      }
+    }
+    if found.is_empty() {
+      return Err(Error::CacheEntryNotFound {
+        entry: entry.to_string(),
+      });
+    }
+    Ok(found)
+  }
+
+  /// Cache entries referenced by installed mods, as `(resolver name, cache id)`
+  async fn referenced_cache_entries(&self) -> Result<HashSet<(String, String)>, Error> {
+    let mut referenced = HashSet::new();
+    for installed_mod in self.installed_mods.list().await? {
+      for resolver in self
+        .resolvers
+        .iter()
+        .filter(|r| r.can_resolve(&installed_mod.url))
+      {
+        referenced.insert((
+          resolver.name().to_string(),
+          resolver.cache_id(&installed_mod.url)?,
+        ));
+      }
+    }
+    Ok(referenced)
+  }
+
+  /// Game roots the cache entry is installed in
+  fn installed_in(
+    resolver: &dyn resolver::Resolver,
+    cache_entry: &CacheEntry,
+    installed_mods: &[InstalledMod],
+  ) -> Vec<PathBuf> {
+    installed_mods
+      .iter()
+      .filter(|x| {
+        resolver.can_resolve(&x.url)
+          && resolver
+            .cache_id(&x.url)
+            .is_ok_and(|id| id == cache_entry.id)
+      })
+      .map(|x| x.game_root.to_owned())
+      .collect()
+  }
+
+  fn report_cache_entry(
+    &self,
+    resolver: &dyn resolver::Resolver,
+    cache_entry: &CacheEntry,
+    installed_mods: &[InstalledMod],
+  ) {
+    self.reporter.report(Event::CacheEntry {
+      resolver: resolver.name().to_string(),
+      id: cache_entry.id.to_owned(),
+      url: cache_entry.url.to_owned(),
+      path: cache_entry.path.to_owned(),
+      size: cache_entry.size,
+      last_updated: cache_entry.last_updated,
+      last_used: cache_entry.last_used,
+      installed_in: Self::installed_in(resolver, cache_entry, installed_mods),
+    });
+  }
+
+  fn report_cache_removed(&self, resolver: &dyn resolver::Resolver, cache_entry: &CacheEntry) {
+    if self.output == OutputFormat::Human {
+      println!(
+        "{}",
+        Msg::CacheRemoved {
+          resolver: resolver.name(),
+          url: &cache_entry.url,
+        }
+      );
+    }
+    self.reporter.report(Event::CacheRemoved {
+      resolver: resolver.name().to_string(),
+      id: cache_entry.id.to_owned(),
+      url: cache_entry.url.to_owned(),
+      size: cache_entry.size,
+    });
+  }
+
+  async fn task_cache_list(&self) -> Result<(), Error> {
+    let installed_mods = self.installed_mods.list().await?;
+    if self.output == OutputFormat::Human {
+      println!("{}", Msg::CacheListHeader);
+    }
+    for resolver in self.resolvers.iter() {
+      for cache_entry in resolver.cache_entries().await? {
+        if self.output == OutputFormat::Human {
+          println!(
+            "{:<10}{:<14}{:>12}  {:<18}{}",
+            resolver.name(),
+            &cache_entry.id[..cache_entry.id.len().min(12)],
+            format_size(cache_entry.size),
+            cache_entry.last_used.format("%Y-%m-%d %H:%M"),
+            cache_entry.url,
+          );
+        }
+        self.report_cache_entry(resolver.as_ref(), &cache_entry, &installed_mods);
+      }
+    }
+    Ok(())
+  }
+
+  async fn task_cache_info(&self, entry: &str) -> Result<(), Error> {
+    let resolvers = self.select_resolvers(None)?;
+    let installed_mods = self.installed_mods.list().await?;
+    for (resolver, cache_entry) in self.find_cache_entries(&resolvers, entry).await? {
+      if self.output == OutputFormat::Human {
+        let last_updated = cache_entry.last_updated.map(|x| x.to_string());
+        println!(
+          "{}\n",
+          Msg::CacheInfo {
+            id: cache_entry.id.as_str(),
+            resolver: resolver.name(),
+            url: &cache_entry.url,
+            path: cache_entry.path.as_path(),
+            size: format_size(cache_entry.size).as_str(),
+            last_updated: last_updated.as_deref(),
+            last_used: cache_entry.last_used.to_string().as_str(),
+            installed_in: Self::installed_in(resolver, &cache_entry, &installed_mods).as_slice(),
+          }
+        );
+      }
+      self.report_cache_entry(resolver, &cache_entry, &installed_mods);
+    }
+    Ok(())
+  }
+
+  async fn task_cache_clear(
+    &self,
+    resolver: Option<&str>,
+    entries: &[String],
+  ) -> Result<(), Error> {
+    let _cache_lock = self.lock_cache().await?;
+    let resolvers = self.select_resolvers(resolver)?;
+    if entries.is_empty() {
+      for resolver in resolvers {
+        resolver.clear_cache().await?;
+        if self.output == OutputFormat::Human {
+          println!(
+            "{}",
+            Msg::CacheCleared {
+              resolver: resolver.name()
+            }
+          );
+        }
+        self.reporter.report(Event::CacheCleared {
+          resolver: resolver.name().to_string(),
+        });
+      }
+      return Ok(());
+    }
+    for entry in entries {
+      for (resolver, cache_entry) in self.find_cache_entries(&resolvers, entry).await? {
+        resolver.remove_cache(cache_entry.id.as_str()).await?;
+        self.report_cache_removed(resolver, &cache_entry);
+      }
+    }
+    Ok(())
+  }
+
+  async fn task_cache_gc(&self) -> Result<(), Error> {
+    let _cache_lock = self.lock_cache().await?;
+    let referenced = self.referenced_cache_entries().await?;
+    let mut freed = 0;
+    for resolver in self.resolvers.iter() {
+      for cache_entry in resolver.cache_entries().await? {
+        if referenced.contains(&(resolver.name().to_string(), cache_entry.id.to_owned())) {
+          continue;
+        }
+        resolver.remove_cache(cache_entry.id.as_str()).await?;
+        freed += cache_entry.size;
+        self.report_cache_removed(resolver.as_ref(), &cache_entry);
+      }
+    }
+    if self.output == OutputFormat::Human {
+      println!(
+        "{}",
+        Msg::CacheFreed {
+          size: format_size(freed).as_str()
+        }
+      );
+    }
+    Ok(())
+  }
+
+  /// Evict least recently used cache entries until the cache fits in
+  /// `cache_size_limit`. Entries used by installed mods are never evicted.
+  pub async fn evict_cache(&self) -> Result<(), Error> {
+    let Some(cache_size_limit) = self.cache_size_limit else {
+      return Ok(());
     };
-    pb.set_message("已找到最新版本");
-    pb.finish();
 
-    let res_mods_root = PathBuf::from(game_root)
-      .join("bin")
-      .join(version)
-      .join("res_mods");
+    enum Evictable<'a> {
+      Download(&'a dyn resolver::Resolver, CacheEntry),
+      Http(cacache::Metadata),
+    }
 
-    let pb = self.multi_progress.add(ProgressBar::new_spinner());
-    pb.enable_steady_tick(Duration::from_millis(100));
-    pb.set_message("安装中");
-    async_copy_dir(mod_cache_root, res_mods_root).await?;
-    pb.set_message("安装完成");
-    pb.finish();
+    let _cache_lock = self.lock_cache().await?;
+    let referenced = self.referenced_cache_entries().await?;
+    let mut total = 0;
+    let mut evictables = Vec::new();
+    for resolver in self.resolvers.iter() {
+      for cache_entry in resolver.cache_entries().await? {
+        total += cache_entry.size;
+        if referenced.contains(&(resolver.name().to_string(), cache_entry.id.to_owned())) {
+          continue;
+        }
+        evictables.push((
+          cache_entry.last_used,
+          cache_entry.size,
+          Evictable::Download(resolver.as_ref(), cache_entry),
+        ));
+      }
+    }
+    for metadata in cacache::list_sync(self.http_cache_dir.as_path()) {
+      let metadata = metadata?;
+      total += metadata.size as u64;
+      evictables.push((
+        DateTime::from_timestamp_millis(metadata.time as i64).unwrap_or_default(),
+        metadata.size as u64,
+        Evictable::Http(metadata),
+      ));
+    }
+    if total <= cache_size_limit {
+      return Ok(());
+    }
+    debug!(
+      "cache size {} exceeds limit {}",
+      format_size(total),
+      format_size(cache_size_limit)
+    );
 
+    evictables.sort_by_key(|(last_used, _, _)| *last_used);
+    let mut evicted_http = Vec::new();
+    let mut evictables = evictables.into_iter();
+    while total > cache_size_limit
+      && let Some((_, size, evictable)) = evictables.next()
+    {
+      match evictable {
+        Evictable::Download(resolver, cache_entry) => {
+          debug!("evict {} {}", resolver.name(), cache_entry.url);
+          resolver.remove_cache(cache_entry.id.as_str()).await?;
+          self.reporter.report(Event::CacheRemoved {
+            resolver: resolver.name().to_string(),
+            id: cache_entry.id,
+            url: cache_entry.url,
+            size: cache_entry.size,
+          });
+        }
+        Evictable::Http(metadata) => {
+          cacache::remove(self.http_cache_dir.as_path(), metadata.key.as_str()).await?;
+          evicted_http.push(metadata.integrity);
+        }
+      }
+      total -= size;
+    }
+
+    // 内容可能被多个条目共享，只删除不再被引用的内容
+    let kept = evictables
+      .filter_map(|(_, _, evictable)| match evictable {
+        Evictable::Http(metadata) => Some(metadata.integrity.to_string()),
+        Evictable::Download(..) => None,
+      })
+      .collect::<HashSet<_>>();
+    for integrity in evicted_http {
+      if !kept.contains(&integrity.to_string()) {
+        cacache::remove_hash(self.http_cache_dir.as_path(), &integrity).await?;
+      }
+    }
     Ok(())
   }
 
+  /// Url of `game` or the default game, names are looked up in `[games]`
+  fn game_or_default(&self, game: Option<GameRef>) -> Result<Url, Error> {
+    match game
+      .or(self.default_game.to_owned())
+      .ok_or(Error::GameNotSpecified)?
+    {
+      GameRef::Url(url) => Ok(url),
+      GameRef::Name(name) => Ok(
+        self
+          .games
+          .get(name.as_str())
+          .ok_or(Error::GameNotFound { name })?
+          .url()?,
+      ),
+    }
+  }
+
   /// Run task
   pub async fn run(&self, task: Task) -> Result<(), Error> {
+    let name = task.name().to_string();
+    self.reporter.report(Event::TaskStarted {
+      task: name.to_owned(),
+    });
+    let result = self.run_task(task).await;
+    self.reporter.report(Event::TaskFinished {
+      task: name,
+      ok: result.is_ok(),
+    });
+    result
+  }
+
+  async fn run_task(&self, task: Task) -> Result<(), Error> {
     match task {
-      Task::Install { url, game } => {
-        let game = game
-          .or(self.default_game.to_owned())
-          .ok_or(Error::GameNotSpecified)?;
+      Task::Install { url, game, dry_run } => {
+        let game = self.game_or_default(game)?;
         for url in url {
-          self.task_install(&url, &game).await?;
+          self.task_install(&url, &game, dry_run).await?;
         }
         Ok(())
       }
+      Task::Update { url, game, dry_run } => {
+        let game = self.game_or_default(game)?;
+        self.task_update(url.as_slice(), &game, dry_run).await
+      }
+      Task::Uninstall { url, game, dry_run } => {
+        let game = self.game_or_default(game)?;
+        for url in url {
+          self.task_uninstall(&url, &game, dry_run).await?;
+        }
+        Ok(())
+      }
+      Task::Verify { url, game, repair } => {
+        let game = self.game_or_default(game)?;
+        self.task_verify(url.as_slice(), &game, repair).await
+      }
+      Task::Doctor { game } => self.task_doctor(game).await,
+      Task::CacheList => self.task_cache_list().await,
+      Task::CacheInfo { entry } => self.task_cache_info(entry.as_str()).await,
+      Task::CacheClear { resolver, entries } => {
+        self
+          .task_cache_clear(resolver.as_deref(), entries.as_slice())
+          .await
+      }
+      Task::CacheGc => self.task_cache_gc().await,
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn file_game_root_is_decoded() {
+    let game = Url::parse("file:///opt/My%20Games/Korabli").unwrap();
+    assert_eq!(
+      Kmf::game_root(&game).await.unwrap(),
+      PathBuf::from("/opt/My Games/Korabli")
+    );
+
+    let game = Url::parse("file://server/Korabli").unwrap();
+    assert!(matches!(
+      Kmf::game_root(&game).await,
+      Err(Error::InvalidFileUrl { .. })
+    ));
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn game_root_is_canonical() {
+    let dir = tempfile::tempdir().unwrap();
+    let real_root = dir.path().join("Korabli");
+    fs::create_dir(real_root.as_path()).await.unwrap();
+    std::os::unix::fs::symlink(real_root.as_path(), dir.path().join("link")).unwrap();
+
+    let game = Url::from_file_path(dir.path().join("link")).unwrap();
+    assert_eq!(
+      Kmf::game_root(&game).await.unwrap(),
+      std::fs::canonicalize(real_root).unwrap()
+    );
+  }
+}
@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use indicatif::ProgressDrawTarget;
+use reqwest_middleware::ClientWithMiddleware;
+use tokio::fs;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::{
+  config::{Config, OutputFormat, ProgressDrawTargetType},
+  event::{EventReporter, EventSink, JsonSink, ProgressSink},
+  resolver::{
+    Resolver, ResolverOptions,
+    impls::{kmf::KmfResolver, plugin, web::WebResolver},
+  },
+  util::{ensure_dir, reqwest::build_client},
+};
+
+use super::{Kmf, error::Error, installed::InstalledMods};
+
+/// Builds a [`Kmf`] for use as a library
+pub struct KmfBuilder {
+  config: Config,
+  resolvers: Vec<Box<dyn Resolver>>,
+  default_resolvers: bool,
+  http_client: Option<ClientWithMiddleware>,
+  reporter: Option<EventReporter>,
+}
+
+impl KmfBuilder {
+  pub fn new(config: Config) -> Self {
+    Self {
+      config,
+      resolvers: Vec::new(),
+      default_resolvers: true,
+      http_client: None,
+      reporter: None,
+    }
+  }
+
+  /// Add a resolver, resolvers with higher [`Resolver::priority`] are tried first.
+  /// See [`crate::resolver::KMF_RESOLVER_PRIORITY`] and
+  /// [`crate::resolver::WEB_RESOLVER_PRIORITY`] for the built-in ones
+  pub fn resolver(mut self, resolver: Box<dyn Resolver>) -> Self {
+    self.resolvers.push(resolver);
+    self
+  }
+
+  /// Only use resolvers added by [`KmfBuilder::resolver`]
+  pub fn without_default_resolvers(mut self) -> Self {
+    self.default_resolvers = false;
+    self
+  }
+
+  /// Http client of the built-in resolvers, instead of one built from `[network]` config
+  pub fn http_client(mut self, http_client: ClientWithMiddleware) -> Self {
+    self.http_client = Some(http_client);
+    self
+  }
+
+  /// Where events are reported to, instead of the sink chosen by `output` config
+  pub fn event_sink(mut self, sink: impl EventSink + 'static) -> Self {
+    self.reporter = Some(EventReporter::new(sink));
+    self
+  }
+
+  pub async fn build(self) -> Result<Kmf, Error> {
+    let config = self.config;
+    let default_game = config
+      .default_game
+      .as_ref()
+      .map(|x| {
+        x.parse().map_err(|source| Error::InvalidDefaultGame {
+          value: x.to_owned(),
+          source,
+        })
+      })
+      .transpose()?;
+    let cache_dir = ensure_dir(config.cache_dir.as_path()).await?;
+    let data_dir = ensure_dir(config.data_dir.as_path()).await?;
+    let http_cache_dir = ensure_dir(cache_dir.join("http_ca").as_path())
+      .await?
+      .to_path_buf();
+    let reporter = self.reporter.unwrap_or_else(|| match config.output {
+      OutputFormat::Human => {
+        EventReporter::new(ProgressSink::new(match config.progress_draw_target {
+          ProgressDrawTargetType::Stdout => ProgressDrawTarget::stdout(),
+          ProgressDrawTargetType::Stderr => ProgressDrawTarget::stderr(),
+          ProgressDrawTargetType::Hidden => ProgressDrawTarget::hidden(),
+        }))
+      }
+      // 进度条会混进 JSON 输出，不画进度条
+      OutputFormat::Json => EventReporter::new(JsonSink),
+    });
+    // http 缓存以前按解析器分开存放
+    for resolver_dir in ["kmf_resolver", "web_resolver"] {
+      let legacy_http_cache_dir = cache_dir.join(resolver_dir).join("http_ca");
+      if fs::try_exists(legacy_http_cache_dir.as_path()).await? {
+        info!("removing legacy http cache {:?}", legacy_http_cache_dir);
+        fs::remove_dir_all(legacy_http_cache_dir).await?;
+      }
+    }
+
+    let reqwest_client = match self.http_client {
+      Some(http_client) => http_client,
+      None => build_client(http_cache_dir.to_owned(), config.offline, &config.network)?,
+    };
+    let mut resolvers = self.resolvers;
+    if self.default_resolvers {
+      let resolver_options = ResolverOptions {
+        offline: config.offline,
+        cache_ttl: config.cache_ttl.map(Duration::from_secs),
+        reporter: reporter.to_owned(),
+        wait_for_lock: config.wait_for_lock,
+      };
+      resolvers.push(Box::new(
+        KmfResolver::new(
+          cache_dir.join("kmf_resolver"),
+          reqwest_client.to_owned(),
+          resolver_options.to_owned(),
+        )
+        .await?,
+      ));
+      resolvers.push(Box::new(
+        WebResolver::new(
+          cache_dir.join("web_resolver"),
+          reqwest_client.to_owned(),
+          resolver_options.to_owned(),
+        )
+        .await?,
+      ));
+      // 插件只处理内置解析器不支持的 scheme
+      for (scheme, program) in plugin::discover(&config.resolvers) {
+        let Ok(probe) = Url::parse(format!("{scheme}://kmf/").as_str()) else {
+          warn!("ignore resolver plugin {:?}: invalid scheme", program);
+          continue;
+        };
+        if resolvers
+          .iter()
+          .any(|r| r.name() == scheme || r.can_resolve(&probe))
+        {
+          debug!("resolver plugin {:?} is shadowed", program);
+          continue;
+        }
+        let plugin_cache_dir = cache_dir.join("plugins").join(scheme.as_str());
+        resolvers.push(Box::new(
+          plugin::PluginResolver::new(
+            scheme.to_owned(),
+            program,
+            plugin_cache_dir,
+            resolver_options.to_owned(),
+            Duration::from_secs(config.plugin_timeout),
+          )
+          .await?,
+        ));
+      }
+    }
+    resolvers.sort_by_key(|resolver| std::cmp::Reverse(resolver.priority()));
+
+    Ok(Kmf {
+      default_game,
+      games: config.games.to_owned(),
+      cache_lock_file: cache_dir.join("kmf.lock"),
+      wait_for_lock: config.wait_for_lock,
+      installed_mods: InstalledMods::new(data_dir, config.wait_for_lock),
+      cache_dir: cache_dir.to_path_buf(),
+      data_dir: data_dir.to_path_buf(),
+      http_client: reqwest_client,
+      http_cache_dir,
+      cache_size_limit: config.cache_size_limit,
+      offline: config.offline,
+      output: config.output,
+      reporter,
+      resolvers,
+    })
+  }
+}
@@ -0,0 +1,247 @@
+use std::{
+  collections::HashSet,
+  path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tokio::fs;
+use tracing::debug;
+
+use super::GameInstall;
+
+/// Where a detected game was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameSource {
+  /// Native client in a Steam library
+  Steam,
+  /// Steam's Proton prefix
+  Proton,
+  /// Lutris prefix
+  Lutris,
+  /// Any other Wine prefix
+  Wine,
+}
+
+impl GameSource {
+  pub fn name(&self) -> &'static str {
+    match self {
+      GameSource::Steam => "steam",
+      GameSource::Proton => "proton",
+      GameSource::Lutris => "lutris",
+      GameSource::Wine => "wine",
+    }
+  }
+}
+
+/// Game client found by [`detect_games`]
+#[derive(Debug, Clone, Serialize)]
+pub struct DetectedGame {
+  pub source: GameSource,
+  pub install: GameInstall,
+}
+
+/// Steam installs, `~/.steam/steam` is usually a link to one of the others
+const STEAM_ROOTS: &[&str] = &[
+  ".steam/steam",
+  ".local/share/Steam",
+  ".var/app/com.valvesoftware.Steam/.local/share/Steam",
+];
+
+/// Dirs under `drive_c` that games are installed into
+const DRIVE_C_DIRS: &[&str] = &["", "Games", "Program Files", "Program Files (x86)"];
+
+/// Values of `"path"` keys in Steam's `libraryfolders.vdf`
+fn library_folders(vdf: &str) -> Vec<PathBuf> {
+  let mut folders = Vec::new();
+  // 键后面跟字符串是值，跟 `{` 是嵌套的块
+  let mut key: Option<String> = None;
+  let mut chars = vdf.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '"' => {}
+      '{' | '}' => {
+        key = None;
+        continue;
+      }
+      _ => continue,
+    }
+    let mut string = String::new();
+    while let Some(c) = chars.next() {
+      match c {
+        '"' => break,
+        '\\' => string.extend(chars.next()),
+        c => string.push(c),
+      }
+    }
+    match key.take() {
+      Some(key) if key.eq_ignore_ascii_case("path") => folders.push(PathBuf::from(string)),
+      Some(_) => {}
+      None => key = Some(string),
+    }
+  }
+  folders
+}
+
+/// Subdirs of `dir`, nothing when it cannot be read
+async fn subdirs(dir: &Path) -> Vec<PathBuf> {
+  let mut dirs = Vec::new();
+  let Ok(mut entries) = fs::read_dir(dir).await else {
+    return dirs;
+  };
+  while let Ok(Some(entry)) = entries.next_entry().await {
+    if entry.file_type().await.is_ok_and(|x| x.is_dir()) {
+      dirs.push(entry.path());
+    }
+  }
+  dirs.sort();
+  dirs
+}
+
+/// Wine prefixes of Lutris games, from `prefix:` of their configs
+async fn lutris_prefixes(home: &Path) -> Vec<PathBuf> {
+  let mut prefixes = Vec::new();
+  let Ok(mut entries) = fs::read_dir(home.join(".config/lutris/games")).await else {
+    return prefixes;
+  };
+  while let Ok(Some(entry)) = entries.next_entry().await {
+    let Ok(content) = fs::read_to_string(entry.path()).await else {
+      continue;
+    };
+    prefixes.extend(content.lines().filter_map(|line| {
+      let prefix = line.trim().strip_prefix("prefix:")?.trim();
+      (!prefix.is_empty()).then(|| PathBuf::from(prefix.trim_matches(['"', '\''])))
+    }));
+  }
+  prefixes
+}
+
+/// Candidate game roots with where they come from
+async fn candidates(home: &Path) -> Vec<(PathBuf, GameSource)> {
+  let mut prefixes = Vec::new();
+  let mut candidates = Vec::new();
+
+  for steam_root in STEAM_ROOTS.iter().map(|x| home.join(x)) {
+    let mut libraries = vec![steam_root.to_owned()];
+    if let Ok(vdf) = fs::read_to_string(steam_root.join("steamapps/libraryfolders.vdf")).await {
+      libraries.extend(library_folders(vdf.as_str()));
+    }
+    for library in libraries {
+      let steamapps = library.join("steamapps");
+      for dir in subdirs(steamapps.join("common").as_path()).await {
+        candidates.push((dir, GameSource::Steam));
+      }
+      for dir in subdirs(steamapps.join("compatdata").as_path()).await {
+        prefixes.push((dir.join("pfx"), GameSource::Proton));
+      }
+    }
+  }
+
+  for prefix in lutris_prefixes(home).await {
+    prefixes.push((prefix, GameSource::Lutris));
+  }
+  // Lutris 默认把每个游戏的前缀放在 ~/Games/<游戏> 下
+  for dir in subdirs(home.join("Games").as_path()).await {
+    prefixes.push((dir, GameSource::Lutris));
+  }
+  if let Some(prefix) = std::env::var_os("WINEPREFIX") {
+    prefixes.push((PathBuf::from(prefix), GameSource::Wine));
+  }
+  prefixes.push((home.join(".wine"), GameSource::Wine));
+  for dir in subdirs(home.join(".local/share/wineprefixes").as_path()).await {
+    prefixes.push((dir, GameSource::Wine));
+  }
+
+  for (prefix, source) in prefixes {
+    let drive_c = prefix.join("drive_c");
+    for dir in DRIVE_C_DIRS.iter().map(|x| drive_c.join(x)) {
+      for dir in subdirs(dir.as_path()).await {
+        candidates.push((dir, source));
+      }
+    }
+  }
+  candidates
+}
+
+/// Scan Steam libraries, Wine, Proton and Lutris prefixes of the user for game clients,
+/// each client is reported once even when reachable by several paths
+pub async fn detect_games() -> Vec<DetectedGame> {
+  let Some(base_dirs) = directories::BaseDirs::new() else {
+    return Vec::new();
+  };
+  let mut seen = HashSet::new();
+  let mut games = Vec::new();
+  for (game_root, source) in candidates(base_dirs.home_dir()).await {
+    let Ok(real_root) = fs::canonicalize(game_root.as_path()).await else {
+      continue;
+    };
+    if seen.contains(&real_root) {
+      continue;
+    }
+    let Ok(install) = GameInstall::read(real_root.as_path()).await else {
+      continue;
+    };
+    if install.builds.is_empty() {
+      continue;
+    }
+    if seen.insert(real_root) {
+      debug!("detected game {:?} from {}", game_root, source.name());
+      games.push(DetectedGame { source, install });
+    }
+  }
+  games
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reads_library_folders() {
+    let vdf = r#""libraryfolders"
+{
+	"0"
+	{
+		"path"		"/home/u/.local/share/Steam"
+		"label"		""
+		"contentid"		"4217406521880312145"
+		"totalsize"		"0"
+		"apps"
+		{
+			"228980"		"290471943"
+		}
+	}
+	"1"
+	{
+		"path"		"/mnt/games/Steam Library"
+		"label"		"path"
+		"apps"
+		{
+			"552990"		"69234177432"
+		}
+	}
+	"2"
+	{
+		"PATH"		"D:\\SteamLibrary"
+	}
+}
+"#;
+    assert_eq!(
+      library_folders(vdf),
+      [
+        PathBuf::from("/home/u/.local/share/Steam"),
+        PathBuf::from("/mnt/games/Steam Library"),
+        PathBuf::from("D:\\SteamLibrary"),
+      ]
+    );
+  }
+
+  #[test]
+  fn reads_truncated_library_folders() {
+    assert!(library_folders("").is_empty());
+    assert_eq!(
+      library_folders(r#""libraryfolders" { "0" { "path" "/games"#),
+      [PathBuf::from("/games")]
+    );
+  }
+}
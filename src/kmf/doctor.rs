@@ -0,0 +1,399 @@
+use std::{
+  path::Path,
+  time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use headers::HeaderMapExt;
+use http_cache_reqwest::CacheMode;
+use tokio::fs;
+
+use crate::{
+  config::OutputFormat,
+  event::{CheckStatus, Event},
+  i18n::{Language, Msg},
+  resolver::impls::kmf::STATION_URL,
+  util::{format_size, reqwest::middleware::IndirectHost},
+};
+
+use super::{GameInstall, GameRef, Kmf, error::Error};
+
+/// Partial downloads untouched for this long are leftovers of interrupted runs
+const LEFTOVER_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// Clock skew tolerated when comparing with server times
+const MAX_CLOCK_SKEW: i64 = 5 * 60;
+
+/// Write and remove a probe file in `dir`
+async fn probe_writable(dir: &Path) -> Result<(), std::io::Error> {
+  let probe = dir.join(".kmf-write-test");
+  fs::write(probe.as_path(), b"").await?;
+  fs::remove_file(probe).await
+}
+
+impl Kmf {
+  fn report_check(&self, check: &str, status: CheckStatus, message: Msg, fix: Option<Msg>) {
+    if self.output == OutputFormat::Human {
+      let mark = match status {
+        CheckStatus::Ok => "✓",
+        CheckStatus::Warning => "!",
+        CheckStatus::Error => "✗",
+        CheckStatus::Skipped => "-",
+      };
+      println!("{mark} {message}");
+      if let Some(fix) = fix.as_ref() {
+        println!("  {}: {fix}", Msg::Hint);
+      }
+    }
+    self.reporter.report(Event::Check {
+      check: check.to_string(),
+      status,
+      message: message.localize(Language::En),
+      fix: fix.map(|x| x.localize(Language::En)),
+    });
+  }
+
+  /// Returns whether the game checks passed
+  async fn check_game(&self, game: Option<GameRef>) -> bool {
+    let game = match self.game_or_default(game) {
+      Ok(game) => game,
+      Err(Error::GameNotSpecified) => {
+        self.report_check(
+          "game_root",
+          CheckStatus::Skipped,
+          Msg::DoctorNoGame,
+          Some(Msg::HintGameNotSpecified),
+        );
+        return true;
+      }
+      Err(err) => {
+        let error = err
+          .message()
+          .map(|x| x.localize(Language::En))
+          .unwrap_or_else(|| err.to_string());
+        self.report_check(
+          "game_root",
+          CheckStatus::Error,
+          Msg::DoctorGameFailed {
+            error: error.as_str(),
+          },
+          err.hint(),
+        );
+        return false;
+      }
+    };
+    let game_root = match Self::game_root(&game).await {
+      Ok(game_root) => game_root,
+      Err(err) => {
+        self.report_check(
+          "game_root",
+          CheckStatus::Error,
+          Msg::DoctorGameUrlFailed {
+            url: &game,
+            error: err.to_string().as_str(),
+          },
+          err.hint(),
+        );
+        return false;
+      }
+    };
+    let versions = async {
+      let install = GameInstall::read(game_root.as_path()).await?;
+      let version = Self::game_version(&game, game_root.as_path()).await?;
+      Ok::<_, Error>((install, version))
+    }
+    .await;
+    let version = match versions {
+      Ok((install, version)) => {
+        self.report_check(
+          "game_root",
+          CheckStatus::Ok,
+          Msg::DoctorGameRoot {
+            game_root: game_root.as_path(),
+            versions: install.builds_display().as_str(),
+          },
+          None,
+        );
+        version
+      }
+      Err(err) => {
+        let error = err
+          .message()
+          .map(|x| x.localize(Language::En))
+          .unwrap_or_else(|| err.to_string());
+        self.report_check(
+          "game_root",
+          CheckStatus::Error,
+          Msg::DoctorGameRootFailed {
+            game_root: game_root.as_path(),
+            error: error.as_str(),
+          },
+          err.hint(),
+        );
+        return false;
+      }
+    };
+
+    // res_mods 不存在时安装会创建它，检查其父目录
+    let res_mods_root = Self::res_mods_root(game_root.as_path(), version.as_str());
+    let dir = match fs::try_exists(res_mods_root.as_path()).await {
+      Ok(true) => res_mods_root.as_path(),
+      _ => res_mods_root.parent().expect("res_mods has parent"),
+    };
+    match probe_writable(dir).await {
+      Ok(()) => {
+        self.report_check(
+          "res_mods_writable",
+          CheckStatus::Ok,
+          Msg::DoctorWritable { path: dir },
+          None,
+        );
+        true
+      }
+      Err(err) => {
+        self.report_check(
+          "res_mods_writable",
+          CheckStatus::Error,
+          Msg::DoctorNotWritable {
+            path: dir,
+            error: err.to_string().as_str(),
+          },
+          Some(Msg::HintGamePermissions),
+        );
+        false
+      }
+    }
+  }
+
+  /// Returns the number of failed checks
+  async fn check_cache(&self) -> usize {
+    let mut failed = 0;
+    match probe_writable(self.cache_dir.as_path()).await {
+      Ok(()) => self.report_check(
+        "cache_dir_writable",
+        CheckStatus::Ok,
+        Msg::DoctorWritable {
+          path: self.cache_dir.as_path(),
+        },
+        None,
+      ),
+      Err(err) => {
+        failed += 1;
+        self.report_check(
+          "cache_dir_writable",
+          CheckStatus::Error,
+          Msg::DoctorNotWritable {
+            path: self.cache_dir.as_path(),
+            error: err.to_string().as_str(),
+          },
+          Some(Msg::HintNotWritable),
+        );
+      }
+    }
+
+    let installed_store = self.data_dir.join("installed.redb");
+    match self.installed_mods.list().await {
+      Ok(installed_mods) => self.report_check(
+        "installed_records",
+        CheckStatus::Ok,
+        Msg::DoctorInstalledRecords {
+          mods: installed_mods.len(),
+        },
+        None,
+      ),
+      Err(err) => {
+        failed += 1;
+        self.report_check(
+          "installed_records",
+          CheckStatus::Error,
+          Msg::DoctorCorruptStore {
+            path: installed_store.as_path(),
+            error: err.to_string().as_str(),
+          },
+          Some(Msg::HintCorruptStore {
+            path: installed_store.as_path(),
+          }),
+        );
+      }
+    }
+
+    for resolver in self.resolvers.iter() {
+      let name = resolver.name();
+      let cache_entries = match resolver.cache_entries().await {
+        Ok(cache_entries) => cache_entries,
+        Err(err) => {
+          failed += 1;
+          self.report_check(
+            "cache_records",
+            CheckStatus::Error,
+            Msg::DoctorCacheRecordsFailed {
+              resolver: name,
+              error: err.to_string().as_str(),
+            },
+            Some(Msg::HintClearCache { resolver: name }),
+          );
+          continue;
+        }
+      };
+      let mut missing = 0;
+      for cache_entry in cache_entries.iter() {
+        if !fs::try_exists(cache_entry.path.as_path())
+          .await
+          .unwrap_or(false)
+        {
+          missing += 1;
+        }
+      }
+      if missing > 0 {
+        self.report_check(
+          "cache_records",
+          CheckStatus::Warning,
+          Msg::DoctorMissingCache {
+            resolver: name,
+            entries: missing,
+          },
+          Some(Msg::HintClearCache { resolver: name }),
+        );
+      } else {
+        self.report_check(
+          "cache_records",
+          CheckStatus::Ok,
+          Msg::DoctorCacheRecords {
+            resolver: name,
+            entries: cache_entries.len(),
+          },
+          None,
+        );
+      }
+    }
+
+    let (files, size) = self.leftovers().await;
+    if files > 0 {
+      self.report_check(
+        "leftovers",
+        CheckStatus::Warning,
+        Msg::DoctorLeftovers {
+          files,
+          size: format_size(size).as_str(),
+        },
+        Some(Msg::HintLeftovers {
+          dir: self.cache_dir.as_path(),
+        }),
+      );
+    } else {
+      self.report_check("leftovers", CheckStatus::Ok, Msg::DoctorNoLeftovers, None);
+    }
+    failed
+  }
+
+  /// Number and size of stale partial downloads of every resolver and plugin
+  async fn leftovers(&self) -> (usize, u64) {
+    let mut files = 0;
+    let mut size = 0;
+    let mut resolver_dirs = Vec::new();
+    for dir in [self.cache_dir.to_owned(), self.cache_dir.join("plugins")] {
+      let Ok(mut entries) = fs::read_dir(dir).await else {
+        continue;
+      };
+      while let Ok(Some(entry)) = entries.next_entry().await {
+        resolver_dirs.push(entry.path());
+      }
+    }
+    for resolver_dir in resolver_dirs {
+      let Ok(mut entries) = fs::read_dir(resolver_dir.join("partial")).await else {
+        continue;
+      };
+      while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(metadata) = entry.metadata().await else {
+          continue;
+        };
+        let stale = metadata
+          .modified()
+          .ok()
+          .and_then(|x| x.elapsed().ok())
+          .is_some_and(|x| x > LEFTOVER_AGE);
+        if metadata.is_file() && stale {
+          files += 1;
+          size += metadata.len();
+        }
+      }
+    }
+    (files, size)
+  }
+
+  /// Returns the number of failed checks
+  async fn check_network(&self) -> usize {
+    if self.offline {
+      self.report_check("station", CheckStatus::Skipped, Msg::DoctorOffline, None);
+      return 0;
+    }
+    let res = match self
+      .http_client
+      .get(STATION_URL)
+      .with_extension(CacheMode::NoStore)
+      .with_extension(IndirectHost)
+      .send()
+      .await
+    {
+      Ok(res) => res,
+      Err(err) => {
+        self.report_check(
+          "station",
+          CheckStatus::Error,
+          Msg::DoctorStationUnreachable {
+            url: STATION_URL,
+            error: err.to_string().as_str(),
+          },
+          Some(Msg::HintNetwork),
+        );
+        return 1;
+      }
+    };
+    let status = res.status();
+    if !status.is_success() {
+      self.report_check(
+        "station",
+        CheckStatus::Error,
+        Msg::DoctorStationUnreachable {
+          url: STATION_URL,
+          error: format!("HTTP {status}").as_str(),
+        },
+        Some(Msg::HintNetwork),
+      );
+      return 1;
+    }
+    self.report_check(
+      "station",
+      CheckStatus::Ok,
+      Msg::DoctorStationReachable { url: STATION_URL },
+      None,
+    );
+
+    let Some(date) = res.headers().typed_get::<headers::Date>() else {
+      return 0;
+    };
+    let date = DateTime::<Utc>::from(SystemTime::from(date));
+    let skew = (Utc::now() - date).num_seconds();
+    if skew.abs() > MAX_CLOCK_SKEW {
+      self.report_check(
+        "clock",
+        CheckStatus::Warning,
+        Msg::DoctorClockSkew { seconds: skew },
+        Some(Msg::HintClock),
+      );
+    } else {
+      self.report_check("clock", CheckStatus::Ok, Msg::DoctorClockOk, None);
+    }
+    0
+  }
+
+  /// Check the game, cache and network, failing when any check fails
+  pub(super) async fn task_doctor(&self, game: Option<GameRef>) -> Result<(), Error> {
+    let mut failed = usize::from(!self.check_game(game).await);
+    failed += self.check_cache().await;
+    failed += self.check_network().await;
+    if failed > 0 {
+      return Err(Error::ChecksFailed { failed });
+    }
+    Ok(())
+  }
+}
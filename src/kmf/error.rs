@@ -1,15 +1,47 @@
-use crate::{resolver, util};
+use crate::{error::ErrorCategory, i18n::Msg, resolver, util};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
   #[error("game not specified")]
   GameNotSpecified,
+  #[error("invalid config 'default_game' {value:?}: {source}")]
+  InvalidDefaultGame {
+    value: String,
+    source: url::ParseError,
+  },
+  #[error("game not found: {name}")]
+  GameNotFound { name: String },
+  #[error("no game detected")]
+  GameNotDetected,
+  #[error("several games detected: {game_roots:?}")]
+  AmbiguousGame { game_roots: Vec<std::path::PathBuf> },
+  #[error("not a local file url: {url}")]
+  InvalidFileUrl { url: Box<url::Url> },
+  #[error("invalid wine game url: {url}")]
+  InvalidWineUrl { url: Box<url::Url> },
+  #[error("not a windows path: {path}")]
+  InvalidWindowsPath { path: String },
+  #[error("drive {drive} is not mapped in wine prefix {prefix:?}")]
+  DriveNotMapped {
+    drive: String,
+    prefix: std::path::PathBuf,
+  },
+  #[error("unsupported game url scheme: {scheme}")]
+  UnsupportedGameScheme { scheme: String },
   #[error("version not found: {version}")]
   VersionNotFound { version: String },
+  #[error("no game version found in {game_root:?}")]
+  NoGameVersion { game_root: std::path::PathBuf },
   #[error("std::io: {0}")]
   Io(#[from] std::io::Error),
   #[error("kmf::util::get_game_versions: {0}")]
   UtilGetGameVersions(#[from] util::GetGameVersionsError),
+  #[error("{0}")]
+  Lock(#[from] util::error::LockError),
+  #[error("store: {0}")]
+  Store(#[from] util::error::StoreError),
+  #[error("http client: {0}")]
+  BuildClient(#[from] util::error::BuildClientError),
   #[error("kmf::util::unzip_file: {0}")]
   UtilUnzipFile(#[from] util::error::UnzipFileError),
   #[error("reqwest: {0}")]
@@ -20,8 +52,132 @@ pub enum Error {
   TomlDe(#[from] toml::de::Error),
   #[error("toml::ser: {0}")]
   TomlSer(#[from] toml::ser::Error),
+  #[error("cacache: {0}")]
+  Cacache(#[from] cacache::Error),
   #[error("resolver: {0}")]
   Resolver(#[from] resolver::Error),
   #[error("mod not found")]
   ModNotFound,
+  #[error("resolver {resolver} cannot pick versions: {url}")]
+  VersionsNotSupported {
+    resolver: String,
+    url: Box<url::Url>,
+  },
+  #[error("resolver not found: {resolver}")]
+  ResolverNotFound { resolver: String },
+  #[error("cache entry not found: {entry}")]
+  CacheEntryNotFound { entry: String },
+  #[error("{failed} doctor checks failed")]
+  ChecksFailed { failed: usize },
+  #[error("{mods} installed mods differ from their sources")]
+  Drifted { mods: usize },
+  #[error("mod not installed in {game_root:?}: {url}")]
+  ModNotInstalled {
+    url: Box<url::Url>,
+    game_root: std::path::PathBuf,
+  },
+}
+
+impl Error {
+  pub fn category(&self) -> ErrorCategory {
+    match self {
+      Error::GameNotSpecified
+      | Error::InvalidDefaultGame { .. }
+      | Error::UnsupportedGameScheme { .. }
+      | Error::AmbiguousGame { .. }
+      | Error::InvalidFileUrl { .. }
+      | Error::InvalidWineUrl { .. }
+      | Error::InvalidWindowsPath { .. }
+      | Error::DriveNotMapped { .. }
+      | Error::VersionsNotSupported { .. }
+      | Error::BuildClient(_) => ErrorCategory::Config,
+      Error::UtilGetGameVersions(util::GetGameVersionsError::Io(_)) => ErrorCategory::Other,
+      Error::UtilGetGameVersions(_) => ErrorCategory::Config,
+      Error::VersionNotFound { .. }
+      | Error::NoGameVersion { .. }
+      | Error::GameNotFound { .. }
+      | Error::GameNotDetected
+      | Error::ModNotFound
+      | Error::ResolverNotFound { .. }
+      | Error::CacheEntryNotFound { .. }
+      | Error::ModNotInstalled { .. } => ErrorCategory::NotFound,
+      Error::Lock(util::error::LockError::Locked { .. })
+      | Error::Store(util::error::StoreError::Lock(util::error::LockError::Locked { .. })) => {
+        ErrorCategory::Conflict
+      }
+      Error::Reqwest(err) => ErrorCategory::of_http(err.status()),
+      Error::ReqwestMiddleware(err) => ErrorCategory::of_http(err.status()),
+      Error::Drifted { .. } => ErrorCategory::Integrity,
+      Error::UtilUnzipFile(util::error::UnzipFileError::AsyncZipError(_)) => {
+        ErrorCategory::Integrity
+      }
+      Error::Resolver(err) => err.category(),
+      Error::Io(_)
+      | Error::Lock(_)
+      | Error::Store(_)
+      | Error::UtilUnzipFile(_)
+      | Error::TomlDe(_)
+      | Error::TomlSer(_)
+      | Error::Cacache(_)
+      | Error::ChecksFailed { .. } => ErrorCategory::Other,
+    }
+  }
+
+  /// Localized message, `None` for errors only meaningful in English
+  pub fn message(&self) -> Option<Msg<'_>> {
+    match self {
+      Error::GameNotSpecified => Some(Msg::GameNotSpecified),
+      Error::InvalidDefaultGame { value, .. } => Some(Msg::InvalidDefaultGame { value }),
+      Error::GameNotFound { name } => Some(Msg::GameNotFound { name }),
+      Error::GameNotDetected => Some(Msg::GameNotDetected),
+      Error::AmbiguousGame { game_roots } => Some(Msg::AmbiguousGame { game_roots }),
+      Error::InvalidFileUrl { url } => Some(Msg::InvalidFileUrl { url }),
+      Error::InvalidWineUrl { url } => Some(Msg::InvalidWineUrl { url }),
+      Error::InvalidWindowsPath { path } => Some(Msg::InvalidWindowsPath { path }),
+      Error::DriveNotMapped { drive, prefix } => Some(Msg::DriveNotMapped { drive, prefix }),
+      Error::UnsupportedGameScheme { scheme } => Some(Msg::UnsupportedGameScheme { scheme }),
+      Error::VersionNotFound { version } => Some(Msg::VersionNotFound { version }),
+      Error::NoGameVersion { game_root } => Some(Msg::NoGameVersion { game_root }),
+      Error::UtilGetGameVersions(err) => err.message(),
+      Error::Lock(err) | Error::Store(util::error::StoreError::Lock(err)) => err.message(),
+      Error::Resolver(err) => err.message(),
+      Error::ModNotFound => Some(Msg::ModNotFound),
+      Error::VersionsNotSupported { resolver, url } => {
+        Some(Msg::VersionsNotSupported { resolver, url })
+      }
+      Error::ResolverNotFound { resolver } => Some(Msg::ResolverNotFound { resolver }),
+      Error::CacheEntryNotFound { entry } => Some(Msg::CacheEntryNotFound { entry }),
+      Error::ModNotInstalled { url, game_root } => Some(Msg::ModNotInstalled { url, game_root }),
+      Error::Drifted { mods } => Some(Msg::Drifted { mods: *mods }),
+      Error::ChecksFailed { failed } => Some(Msg::ChecksFailed { failed: *failed }),
+      _ => None,
+    }
+  }
+
+  /// What the user may do about the error
+  pub fn hint(&self) -> Option<Msg<'static>> {
+    match self {
+      Error::GameNotSpecified => Some(Msg::HintGameNotSpecified),
+      Error::InvalidDefaultGame { .. } => Some(Msg::HintInvalidDefaultGame),
+      Error::GameNotFound { .. } => Some(Msg::HintGameList),
+      Error::GameNotDetected => Some(Msg::HintGameNotDetected),
+      Error::AmbiguousGame { .. } => Some(Msg::HintAmbiguousGame),
+      Error::InvalidFileUrl { .. } => Some(Msg::HintFileUrl),
+      Error::InvalidWineUrl { .. } | Error::InvalidWindowsPath { .. } => Some(Msg::HintWineUrl),
+      Error::DriveNotMapped { .. } => Some(Msg::HintDosDevices),
+      Error::UnsupportedGameScheme { .. } => Some(Msg::HintUnsupportedGameScheme),
+      Error::UtilGetGameVersions(util::GetGameVersionsError::IllegalGameDirStructure)
+      | Error::NoGameVersion { .. } => Some(Msg::HintGameDirStructure),
+      Error::VersionNotFound { .. } => Some(Msg::HintVersionNotFound),
+      Error::ResolverNotFound { .. } => Some(Msg::HintResolverNotFound),
+      Error::CacheEntryNotFound { .. } => Some(Msg::HintCacheEntryNotFound),
+      Error::Lock(util::error::LockError::Locked { .. })
+      | Error::Store(util::error::StoreError::Lock(util::error::LockError::Locked { .. })) => {
+        Some(Msg::HintWait)
+      }
+      Error::Drifted { .. } => Some(Msg::HintRepair),
+      Error::Resolver(err) => err.hint(),
+      _ => None,
+    }
+  }
 }
@@ -0,0 +1,233 @@
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+  str::FromStr,
+};
+
+use serde::Serialize;
+use tokio::fs;
+use tracing::warn;
+use url::Url;
+
+use crate::util::get_game_versions;
+
+use super::{
+  error::Error,
+  wine::{default_prefix_url, is_windows_path},
+};
+
+/// Launcher metadata in the game root
+const GAME_INFO_FILE: &str = "game_info.xml";
+
+/// Game given by `--game` or `default_game`: an url, or the name of a game in `[games]`.
+/// Windows paths like `C:\Games\Korabli` are looked up in the default wine prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameRef {
+  Url(Url),
+  Name(String),
+}
+
+/// Whether `name` can name a game, names never parse as urls
+pub fn is_game_name(name: &str) -> bool {
+  !name.is_empty()
+    && name
+      .chars()
+      .all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_')
+}
+
+impl FromStr for GameRef {
+  type Err = url::ParseError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if is_game_name(s) {
+      return Ok(GameRef::Name(s.to_string()));
+    }
+    // 从启动器复制的 Windows 路径，在 Wine 默认前缀中查找
+    if is_windows_path(s) {
+      let url = if cfg!(windows) {
+        Url::from_file_path(s).ok()
+      } else {
+        default_prefix_url(s)
+      };
+      if let Some(url) = url {
+        return Ok(GameRef::Url(url));
+      }
+    }
+    Ok(GameRef::Url(s.parse()?))
+  }
+}
+
+impl fmt::Display for GameRef {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      GameRef::Url(url) => write!(f, "{url}"),
+      GameRef::Name(name) => write!(f, "{name}"),
+    }
+  }
+}
+
+/// Client build in `bin/<build>/`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GameBuild {
+  /// Build number, the name of its dir
+  pub build: String,
+  /// Client version like `25.3.0`, only known for the build the launcher installed last
+  pub version: Option<String>,
+}
+
+impl fmt::Display for GameBuild {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.version.as_ref() {
+      Some(version) => write!(f, "{} ({version})", self.build),
+      None => write!(f, "{}", self.build),
+    }
+  }
+}
+
+/// Game client on disk, with what its launcher metadata tells about it
+#[derive(Debug, Clone, Serialize)]
+pub struct GameInstall {
+  pub game_root: PathBuf,
+  /// Launcher id of the game, e.g. `WOWS.WW.PRODUCTION`
+  pub id: Option<String>,
+  /// Realm in `id` in lower case, e.g. `ru` or `ww`
+  pub realm: Option<String>,
+  /// Newest first
+  pub builds: Vec<GameBuild>,
+}
+
+/// Launcher id and installed client version (`<version>.<build>`) in `game_info.xml`
+fn parse_game_info(content: &str) -> Result<(Option<String>, Option<String>), roxmltree::Error> {
+  let document = roxmltree::Document::parse(content)?;
+  let id = document
+    .descendants()
+    .find(|x| x.has_tag_name("id"))
+    .and_then(|x| x.text())
+    .map(|x| x.trim().to_string())
+    .filter(|x| !x.is_empty());
+  let client = document
+    .descendants()
+    .find(|x| x.has_tag_name("version") && x.attribute("name") == Some("client"))
+    .and_then(|x| x.attribute("installed"))
+    .map(|x| x.trim().to_string());
+  Ok((id, client))
+}
+
+impl GameInstall {
+  /// Read builds in `bin/` of `game_root`, and the id, realm and client version from
+  /// `game_info.xml` of the launcher when there is one
+  pub async fn read(game_root: &Path) -> Result<Self, Error> {
+    let mut builds = get_game_versions(game_root)
+      .await?
+      .into_iter()
+      .map(|build| GameBuild {
+        build,
+        version: None,
+      })
+      .collect::<Vec<_>>();
+
+    let game_info = game_root.join(GAME_INFO_FILE);
+    let (id, client) = match fs::read_to_string(game_info.as_path()).await {
+      Ok(content) => parse_game_info(content.as_str()).unwrap_or_else(|err| {
+        warn!("ignore invalid {:?}: {}", game_info, err);
+        (None, None)
+      }),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => (None, None),
+      Err(err) => return Err(err.into()),
+    };
+    if let Some((version, build)) = client.as_deref().and_then(|x| x.rsplit_once('.'))
+      && let Some(game_build) = builds.iter_mut().find(|x| x.build == build)
+    {
+      game_build.version = Some(version.to_string());
+    }
+    let realm = id
+      .as_deref()
+      .and_then(|x| x.split('.').nth(1))
+      .map(|x| x.to_ascii_lowercase());
+
+    Ok(Self {
+      game_root: game_root.to_path_buf(),
+      id,
+      realm,
+      builds,
+    })
+  }
+
+  /// Build given by its number or client version
+  pub fn find_build(&self, version: &str) -> Option<&GameBuild> {
+    self
+      .builds
+      .iter()
+      .find(|x| x.build == version || x.version.as_deref() == Some(version))
+  }
+
+  /// Builds for display, e.g. `7654321 (25.3.0), 7654000`
+  pub fn builds_display(&self) -> String {
+    self
+      .builds
+      .iter()
+      .map(ToString::to_string)
+      .collect::<Vec<_>>()
+      .join(", ")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const GAME_INFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<protocol name="game_info" version="6.8" wgc_publisher_id="lesta">
+  <game>
+    <id>WOWS.RU.PRODUCTION</id>
+    <localization>ru</localization>
+    <content_localizations>
+      <content_localization>ru</content_localization>
+    </content_localizations>
+    <part_versions>
+      <version name="client" installed="25.3.0.9114105" available="25.3.0.9114105"/>
+      <version name="sdcontent" installed="25.3.0.9114105" available="25.3.0.9114105"/>
+      <version name="locale" installed="25.3.0.9114105" available="25.3.0.9114105"/>
+    </part_versions>
+    <accepted_preinstalls/>
+  </game>
+</protocol>
+"#;
+
+  #[test]
+  fn parses_game_info() {
+    assert_eq!(
+      parse_game_info(GAME_INFO).unwrap(),
+      (
+        Some("WOWS.RU.PRODUCTION".to_string()),
+        Some("25.3.0.9114105".to_string())
+      )
+    );
+    assert_eq!(
+      parse_game_info("<protocol><game><id> </id></game></protocol>").unwrap(),
+      (None, None)
+    );
+    assert!(parse_game_info("<protocol>").is_err());
+  }
+
+  #[tokio::test]
+  async fn reads_install() {
+    let game_root = tempfile::tempdir().unwrap();
+    let game_root = game_root.path();
+    for build in ["9114105", "9000000"] {
+      fs::create_dir_all(game_root.join("bin").join(build))
+        .await
+        .unwrap();
+    }
+    fs::write(game_root.join(GAME_INFO_FILE), GAME_INFO)
+      .await
+      .unwrap();
+
+    let install = GameInstall::read(game_root).await.unwrap();
+    assert_eq!(install.realm.as_deref(), Some("ru"));
+    assert_eq!(install.builds_display(), "9114105 (25.3.0), 9000000");
+    assert_eq!(install.find_build("25.3.0").unwrap().build, "9114105");
+    assert_eq!(install.find_build("9000000").unwrap().version, None);
+    assert!(install.find_build("25.2.0").is_none());
+  }
+}
@@ -0,0 +1,179 @@
+use std::{
+  collections::BTreeMap,
+  path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use redb::{ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::OnceCell};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::util::store::Store;
+
+use super::error::Error;
+
+/// Installed mods by `InstalledMod::key`
+const INSTALLED_MODS: TableDefinition<&str, &[u8]> = TableDefinition::new("installed_mods");
+
+/// Mod installed into a game
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstalledMod {
+  pub url: Url,
+  pub game_root: PathBuf,
+  pub version: String,
+  pub installed_at: DateTime<Utc>,
+  /// Installed files, relative to `res_mods`, empty for mods installed by old kmf
+  #[serde(default)]
+  pub files: Vec<PathBuf>,
+  /// Hex encoded sha-256 of installed files, empty for mods installed by old kmf
+  #[serde(default)]
+  pub hashes: BTreeMap<PathBuf, String>,
+}
+
+impl InstalledMod {
+  /// The same mod is recorded once per game version
+  fn key(&self) -> String {
+    format!(
+      "{}\n{}\n{}",
+      self.game_root.display(),
+      self.version,
+      self.url
+    )
+  }
+}
+
+/// `installed.toml` used before the store
+#[derive(Debug, Default, Deserialize)]
+struct LegacyInstalledMods {
+  #[serde(default)]
+  mods: Vec<InstalledMod>,
+}
+
+/// All mods installed by kmf, kept in `installed.redb` under the data dir
+pub struct InstalledMods {
+  store: Store,
+  /// `installed.toml` used before the store
+  legacy_file: PathBuf,
+  migrated: OnceCell<()>,
+}
+
+impl InstalledMods {
+  /// The store is opened and migrated on first use, so a corrupt store only fails the tasks
+  /// reading it
+  pub fn new(data_dir: &Path, wait_for_lock: bool) -> Self {
+    Self {
+      store: Store::new(data_dir.join("installed.redb"), wait_for_lock),
+      legacy_file: data_dir.join("installed.toml"),
+      migrated: OnceCell::new(),
+    }
+  }
+
+  /// Move records from `installed.toml`, creating the table on the way. Game roots recorded
+  /// by old kmf are canonicalized, so a game reached by another path matches its records
+  async fn migrate(&self) -> Result<(), Error> {
+    let legacy_file = self.legacy_file.to_owned();
+    let legacy_installed_mods = if fs::try_exists(legacy_file.as_path()).await? {
+      toml::from_str::<LegacyInstalledMods>(
+        fs::read_to_string(legacy_file.as_path()).await?.as_str(),
+      )
+      .unwrap_or_else(|err| {
+        warn!("ignore corrupt {:?}: {}", legacy_file, err);
+        LegacyInstalledMods::default()
+      })
+    } else {
+      LegacyInstalledMods::default()
+    };
+    let migrated = !legacy_installed_mods.mods.is_empty();
+    self
+      .store
+      .write(move |transaction| {
+        let mut table = transaction.open_table(INSTALLED_MODS)?;
+        for installed_mod in legacy_installed_mods.mods.iter() {
+          let key = installed_mod.key();
+          table.insert(key.as_str(), serde_json::to_vec(installed_mod)?.as_slice())?;
+        }
+        let mut moved = Vec::new();
+        for entry in table.iter()? {
+          let (key, value) = entry?;
+          let mut installed_mod = serde_json::from_slice::<InstalledMod>(value.value())?;
+          if let Ok(game_root) = std::fs::canonicalize(installed_mod.game_root.as_path())
+            && game_root != installed_mod.game_root
+          {
+            installed_mod.game_root = game_root;
+            moved.push((key.value().to_owned(), installed_mod));
+          }
+        }
+        for (key, installed_mod) in moved {
+          table.remove(key.as_str())?;
+          let key = installed_mod.key();
+          table.insert(key.as_str(), serde_json::to_vec(&installed_mod)?.as_slice())?;
+        }
+        Ok(())
+      })
+      .await?;
+    if migrated {
+      info!("migrated {:?} into store", legacy_file);
+    }
+    match fs::remove_file(legacy_file).await {
+      Ok(_) => {}
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+      Err(err) => return Err(err.into()),
+    }
+    Ok(())
+  }
+
+  async fn store(&self) -> Result<&Store, Error> {
+    self.migrated.get_or_try_init(|| self.migrate()).await?;
+    Ok(&self.store)
+  }
+
+  pub async fn list(&self) -> Result<Vec<InstalledMod>, Error> {
+    Ok(
+      self
+        .store()
+        .await?
+        .read(|transaction| {
+          let table = transaction.open_table(INSTALLED_MODS)?;
+          let mut installed_mods = Vec::new();
+          for entry in table.iter()? {
+            installed_mods.push(serde_json::from_slice(entry?.1.value())?);
+          }
+          Ok(installed_mods)
+        })
+        .await?,
+    )
+  }
+
+  /// Insert or replace the record of the same mod in the same game version
+  pub async fn insert(&self, installed_mod: InstalledMod) -> Result<(), Error> {
+    Ok(
+      self
+        .store()
+        .await?
+        .write(move |transaction| {
+          let key = installed_mod.key();
+          let mut table = transaction.open_table(INSTALLED_MODS)?;
+          table.insert(key.as_str(), serde_json::to_vec(&installed_mod)?.as_slice())?;
+          Ok(())
+        })
+        .await?,
+    )
+  }
+
+  pub async fn remove(&self, installed_mod: &InstalledMod) -> Result<(), Error> {
+    let key = installed_mod.key();
+    Ok(
+      self
+        .store()
+        .await?
+        .write(move |transaction| {
+          let mut table = transaction.open_table(INSTALLED_MODS)?;
+          table.remove(key.as_str())?;
+          Ok(())
+        })
+        .await?,
+    )
+  }
+}
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::{event::Conflict, util::file_sha256};
+
+use super::installed::InstalledMod;
+
+/// Changes to `res_mods` of one game version, all paths are relative to it
+#[derive(Debug, Default)]
+pub struct Plan {
+  pub create: Vec<PathBuf>,
+  pub overwrite: Vec<PathBuf>,
+  pub remove: Vec<PathBuf>,
+  /// Files also installed by other mods
+  pub conflicts: Vec<Conflict>,
+}
+
+impl Plan {
+  /// Install `files` from `from`, replacing `old_files` installed before by the same mod.
+  /// Files with the same content are left as is.
+  pub async fn install(
+    from: &Path,
+    res_mods_root: &Path,
+    files: &[PathBuf],
+    old_files: &[PathBuf],
+    others: &[InstalledMod],
+  ) -> Result<Self, std::io::Error> {
+    let mut plan = Self::default();
+    for file in files {
+      let target = res_mods_root.join(file);
+      if fs::try_exists(target.as_path()).await? {
+        if file_sha256(from.join(file).as_path()).await? != file_sha256(target.as_path()).await? {
+          plan.overwrite.push(file.to_owned());
+        }
+      } else {
+        plan.create.push(file.to_owned());
+      }
+      plan.add_conflicts(file, others);
+    }
+    for file in old_files.iter().filter(|x| !files.contains(x)) {
+      if !plan.add_conflicts(file, others) && fs::try_exists(res_mods_root.join(file)).await? {
+        plan.remove.push(file.to_owned());
+      }
+    }
+    Ok(plan)
+  }
+
+  /// Remove `files`, files still used by other mods are kept
+  pub async fn uninstall(
+    res_mods_root: &Path,
+    files: &[PathBuf],
+    others: &[InstalledMod],
+  ) -> Result<Self, std::io::Error> {
+    let mut plan = Self::default();
+    for file in files {
+      if !plan.add_conflicts(file, others) && fs::try_exists(res_mods_root.join(file)).await? {
+        plan.remove.push(file.to_owned());
+      }
+    }
+    Ok(plan)
+  }
+
+  /// Returns whether any other mod has installed the file
+  fn add_conflicts(&mut self, file: &Path, others: &[InstalledMod]) -> bool {
+    let mut found = false;
+    for other in others.iter().filter(|x| x.files.iter().any(|x| x == file)) {
+      self.conflicts.push(Conflict {
+        path: file.to_path_buf(),
+        url: other.url.to_owned(),
+      });
+      found = true;
+    }
+    found
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.create.is_empty() && self.overwrite.is_empty() && self.remove.is_empty()
+  }
+
+  /// Apply the plan, files to create or overwrite are copied from `from`
+  pub async fn apply(
+    &self,
+    from: Option<&Path>,
+    res_mods_root: &Path,
+  ) -> Result<(), std::io::Error> {
+    if let Some(from) = from {
+      for file in self.create.iter().chain(self.overwrite.iter()) {
+        let target = res_mods_root.join(file);
+        fs::create_dir_all(target.parent().expect("File always has parent")).await?;
+        fs::copy(from.join(file), target).await?;
+      }
+    }
+    for file in self.remove.iter() {
+      let target = res_mods_root.join(file);
+      match fs::remove_file(target.as_path()).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+      }
+      // 删除空目录，直到 res_mods
+      let mut dir = target.parent();
+      while let Some(x) = dir
+        && x != res_mods_root
+        && x.starts_with(res_mods_root)
+        && fs::remove_dir(x).await.is_ok()
+      {
+        dir = x.parent();
+      }
+    }
+    Ok(())
+  }
+}
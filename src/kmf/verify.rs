@@ -0,0 +1,233 @@
+use std::{
+  collections::{BTreeMap, BTreeSet, HashSet},
+  path::{Path, PathBuf},
+};
+
+use tokio::fs;
+
+use crate::util::file_sha256;
+
+use super::installed::InstalledMod;
+
+/// Differences between the files of an installed mod and `res_mods`, paths are relative to it
+#[derive(Debug, Default)]
+pub struct Drift {
+  /// Content differs from what was installed
+  pub modified: Vec<PathBuf>,
+  pub missing: Vec<PathBuf>,
+  /// Files next to the mod's files that no installed mod owns
+  pub unexpected: Vec<PathBuf>,
+  /// Files that cannot be checked, installed by old kmf and not in cache
+  pub unknown: Vec<PathBuf>,
+}
+
+impl Drift {
+  /// Compare `installed_mod` with `res_mods_root`. Files are compared with the hashes recorded
+  /// on install, or with `cache` for mods installed by old kmf. `owned` are the files of every
+  /// mod installed in the same game version
+  pub async fn check(
+    res_mods_root: &Path,
+    installed_mod: &InstalledMod,
+    cache: Option<&Path>,
+    owned: &HashSet<PathBuf>,
+  ) -> Result<Self, std::io::Error> {
+    let mut drift = Self::default();
+    for file in installed_mod.files.iter() {
+      let target = res_mods_root.join(file);
+      if !fs::try_exists(target.as_path()).await? {
+        drift.missing.push(file.to_owned());
+        continue;
+      }
+      let expected = match (installed_mod.hashes.get(file), cache) {
+        (Some(hash), _) => hash.to_owned(),
+        (None, Some(cache)) => match file_sha256(cache.join(file).as_path()).await {
+          Ok(hash) => hash,
+          Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            drift.unknown.push(file.to_owned());
+            continue;
+          }
+          Err(err) => return Err(err),
+        },
+        (None, None) => {
+          drift.unknown.push(file.to_owned());
+          continue;
+        }
+      };
+      if file_sha256(target.as_path()).await? != expected {
+        drift.modified.push(file.to_owned());
+      }
+    }
+
+    let dirs = installed_mod
+      .files
+      .iter()
+      .filter_map(|x| x.parent())
+      .collect::<BTreeSet<_>>();
+    for dir in dirs {
+      let Ok(mut entries) = fs::read_dir(res_mods_root.join(dir)).await else {
+        continue;
+      };
+      while let Some(entry) = entries.next_entry().await? {
+        let file = dir.join(entry.file_name());
+        if entry.file_type().await?.is_file() && !owned.contains(&file) {
+          drift.unexpected.push(file);
+        }
+      }
+    }
+    drift.unexpected.sort();
+    Ok(drift)
+  }
+
+  /// Nothing to report, files that cannot be checked are reported too
+  pub fn is_empty(&self) -> bool {
+    self.modified.is_empty()
+      && self.missing.is_empty()
+      && self.unexpected.is_empty()
+      && self.unknown.is_empty()
+  }
+}
+
+/// Files of `files` whose copy under `source` differs from what was installed, e.g. because
+/// the mod was updated since. Files without a recorded hash only need to exist
+pub async fn changed_sources(
+  source: &Path,
+  installed_mod: &InstalledMod,
+  files: &[PathBuf],
+) -> Result<Vec<PathBuf>, std::io::Error> {
+  let mut changed = Vec::new();
+  for file in files {
+    let hash = match file_sha256(source.join(file).as_path()).await {
+      Ok(hash) => hash,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        changed.push(file.to_owned());
+        continue;
+      }
+      Err(err) => return Err(err),
+    };
+    if installed_mod.hashes.get(file).is_some_and(|x| x != &hash) {
+      changed.push(file.to_owned());
+    }
+  }
+  Ok(changed)
+}
+
+/// Hex encoded sha-256 of `files` under `root`
+pub async fn hash_files(
+  root: &Path,
+  files: &[PathBuf],
+) -> Result<BTreeMap<PathBuf, String>, std::io::Error> {
+  let mut hashes = BTreeMap::new();
+  for file in files {
+    hashes.insert(
+      file.to_owned(),
+      file_sha256(root.join(file).as_path()).await?,
+    );
+  }
+  Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::Utc;
+  use url::Url;
+
+  use super::*;
+
+  async fn write(root: &Path, file: &str, content: &str) {
+    let path = root.join(file);
+    fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+    fs::write(path, content).await.unwrap();
+  }
+
+  async fn installed_mod(root: &Path, files: &[&str]) -> InstalledMod {
+    let files = files.iter().map(PathBuf::from).collect::<Vec<_>>();
+    InstalledMod {
+      url: Url::parse("https://example.com/mod.zip").unwrap(),
+      game_root: PathBuf::from("/game"),
+      version: "1.0".to_owned(),
+      installed_at: Utc::now(),
+      hashes: hash_files(root, &files).await.unwrap(),
+      files,
+    }
+  }
+
+  #[tokio::test]
+  async fn reports_modified_missing_and_unexpected() {
+    let res_mods = tempfile::tempdir().unwrap();
+    let root = res_mods.path();
+    for file in ["a/x.txt", "a/y.txt", "a/z.txt"] {
+      write(root, file, file).await;
+    }
+    let installed_mod = installed_mod(root, &["a/x.txt", "a/y.txt", "a/z.txt"]).await;
+    write(root, "a/y.txt", "changed").await;
+    fs::remove_file(root.join("a/z.txt")).await.unwrap();
+    write(root, "a/other.txt", "other").await;
+    let owned = installed_mod.files.iter().cloned().collect();
+
+    let drift = Drift::check(root, &installed_mod, None, &owned)
+      .await
+      .unwrap();
+    assert_eq!(drift.modified, [PathBuf::from("a/y.txt")]);
+    assert_eq!(drift.missing, [PathBuf::from("a/z.txt")]);
+    assert_eq!(drift.unexpected, [PathBuf::from("a/other.txt")]);
+    assert!(drift.unknown.is_empty());
+  }
+
+  #[tokio::test]
+  async fn reports_intact_files() {
+    let res_mods = tempfile::tempdir().unwrap();
+    write(res_mods.path(), "a/x.txt", "x").await;
+    let installed_mod = installed_mod(res_mods.path(), &["a/x.txt"]).await;
+    let owned = installed_mod.files.iter().cloned().collect();
+
+    let drift = Drift::check(res_mods.path(), &installed_mod, None, &owned)
+      .await
+      .unwrap();
+    assert!(drift.is_empty());
+  }
+
+  #[tokio::test]
+  async fn compares_old_mods_with_cache() {
+    let res_mods = tempfile::tempdir().unwrap();
+    let cache = tempfile::tempdir().unwrap();
+    for file in ["x.txt", "y.txt", "z.txt"] {
+      write(res_mods.path(), file, file).await;
+    }
+    write(cache.path(), "x.txt", "x.txt").await;
+    write(cache.path(), "y.txt", "newer").await;
+    let mut installed_mod = installed_mod(res_mods.path(), &["x.txt", "y.txt", "z.txt"]).await;
+    installed_mod.hashes.clear();
+    let owned = installed_mod.files.iter().cloned().collect();
+
+    let drift = Drift::check(res_mods.path(), &installed_mod, Some(cache.path()), &owned)
+      .await
+      .unwrap();
+    assert_eq!(drift.modified, [PathBuf::from("y.txt")]);
+    assert_eq!(drift.unknown, [PathBuf::from("z.txt")]);
+
+    let drift = Drift::check(res_mods.path(), &installed_mod, None, &owned)
+      .await
+      .unwrap();
+    assert!(drift.modified.is_empty());
+    assert_eq!(drift.unknown.len(), 3);
+    // 只有无法检查的文件时不算完好
+    assert!(!drift.is_empty());
+  }
+
+  #[tokio::test]
+  async fn finds_changed_sources() {
+    let res_mods = tempfile::tempdir().unwrap();
+    let cache = tempfile::tempdir().unwrap();
+    for file in ["x.txt", "y.txt", "z.txt"] {
+      write(res_mods.path(), file, file).await;
+    }
+    let installed_mod = installed_mod(res_mods.path(), &["x.txt", "y.txt", "z.txt"]).await;
+    write(cache.path(), "x.txt", "x.txt").await;
+    write(cache.path(), "y.txt", "newer").await;
+
+    let changed = changed_sources(cache.path(), &installed_mod, &installed_mod.files)
+      .await
+      .unwrap();
+    assert_eq!(changed, [PathBuf::from("y.txt"), PathBuf::from("z.txt")]);
+  }
+}
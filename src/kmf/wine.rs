@@ -0,0 +1,141 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+use url::Url;
+
+use super::error::Error;
+
+/// Whether `path` looks like `C:\Games\Korabli` or `C:/Games/Korabli`
+pub fn is_windows_path(path: &str) -> bool {
+  let mut chars = path.chars();
+  matches!(
+    (chars.next(), chars.next(), chars.next()),
+    (Some(drive), Some(':'), Some('\\' | '/')) if drive.is_ascii_alphabetic()
+  )
+}
+
+/// Wine prefix used when none is given, `$WINEPREFIX` or `~/.wine`
+pub fn default_prefix() -> Option<PathBuf> {
+  std::env::var_os("WINEPREFIX")
+    .map(PathBuf::from)
+    .or_else(|| directories::BaseDirs::new().map(|x| x.home_dir().join(".wine")))
+}
+
+/// `wine` url of a windows path in the default prefix
+pub fn default_prefix_url(windows_path: &str) -> Option<Url> {
+  let mut url = Url::parse("wine:///").expect("wine url is valid");
+  url.set_path(default_prefix()?.to_str()?);
+  url.query_pairs_mut().append_pair("path", windows_path);
+  Some(url)
+}
+
+/// Entry of `dir` named `name` ignoring ascii case like wine does, `dir/name` when none matches
+async fn find_entry(dir: &Path, name: &str) -> PathBuf {
+  let path = dir.join(name);
+  if fs::try_exists(path.as_path()).await.unwrap_or(false) {
+    return path;
+  }
+  if let Ok(mut entries) = fs::read_dir(dir).await {
+    while let Ok(Some(entry)) = entries.next_entry().await {
+      if entry
+        .file_name()
+        .to_string_lossy()
+        .eq_ignore_ascii_case(name)
+      {
+        return entry.path();
+      }
+    }
+  }
+  path
+}
+
+/// Unix path of `windows_path` in the wine `prefix`, the drive is mapped through the
+/// `dosdevices` links of the prefix
+pub async fn unix_path(prefix: &Path, windows_path: &str) -> Result<PathBuf, Error> {
+  if !is_windows_path(windows_path) {
+    return Err(Error::InvalidWindowsPath {
+      path: windows_path.to_string(),
+    });
+  }
+  let (drive, rest) = windows_path.split_at(2);
+  let drive = drive.to_ascii_lowercase();
+  let mut path = match fs::canonicalize(prefix.join("dosdevices").join(drive.as_str())).await {
+    Ok(path) => path,
+    // 没有 dosdevices 的前缀也有 drive_c
+    Err(_) if drive == "c:" && fs::try_exists(prefix.join("drive_c")).await? => {
+      prefix.join("drive_c")
+    }
+    Err(_) => {
+      return Err(Error::DriveNotMapped {
+        drive: drive.to_ascii_uppercase(),
+        prefix: prefix.to_path_buf(),
+      });
+    }
+  };
+  for name in rest
+    .split(['\\', '/'])
+    .filter(|x| !x.is_empty() && *x != ".")
+  {
+    path = find_entry(path.as_path(), name).await;
+  }
+  Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn windows_paths() {
+    assert!(is_windows_path(r"C:\Games\Korabli"));
+    assert!(is_windows_path("d:/Games"));
+    assert!(!is_windows_path("C:"));
+    assert!(!is_windows_path("C:Games"));
+    assert!(!is_windows_path("/home/u/Games"));
+    assert!(!is_windows_path("1:/Games"));
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn maps_drives_through_dosdevices() {
+    let prefix = tempfile::tempdir().unwrap();
+    let prefix = prefix.path();
+    let game_root = prefix.join("drive_c/Games/Korabli");
+    fs::create_dir_all(game_root.as_path()).await.unwrap();
+    fs::create_dir(prefix.join("dosdevices")).await.unwrap();
+    std::os::unix::fs::symlink("../drive_c", prefix.join("dosdevices/c:")).unwrap();
+    std::os::unix::fs::symlink("/", prefix.join("dosdevices/z:")).unwrap();
+    let game_root = std::fs::canonicalize(game_root).unwrap();
+
+    assert_eq!(
+      unix_path(prefix, r"C:\games\KORABLI").await.unwrap(),
+      game_root
+    );
+    assert_eq!(
+      unix_path(prefix, "z:/tmp").await.unwrap(),
+      PathBuf::from("/tmp")
+    );
+    assert!(matches!(
+      unix_path(prefix, "D:/Games").await,
+      Err(Error::DriveNotMapped { drive, .. }) if drive == "D:"
+    ));
+    assert!(matches!(
+      unix_path(prefix, "Games/Korabli").await,
+      Err(Error::InvalidWindowsPath { .. })
+    ));
+  }
+
+  #[tokio::test]
+  async fn falls_back_to_drive_c() {
+    let prefix = tempfile::tempdir().unwrap();
+    let prefix = prefix.path();
+    fs::create_dir_all(prefix.join("drive_c/Games"))
+      .await
+      .unwrap();
+
+    assert_eq!(
+      unix_path(prefix, "C:/Games/Korabli").await.unwrap(),
+      prefix.join("drive_c/Games/Korabli")
+    );
+  }
+}
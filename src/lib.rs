@@ -1,6 +1,8 @@
-mod cli;
+pub mod cli;
 pub mod config;
 pub mod error;
+pub mod event;
+pub mod i18n;
 pub mod kmf;
 pub mod resolver;
 pub mod task;
@@ -8,5 +10,5 @@ mod util;
 
 pub use config::Config;
 pub use error::Error;
-pub use kmf::Kmf;
+pub use kmf::{GameInstall, Kmf, KmfBuilder};
 pub use task::Task;
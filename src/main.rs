@@ -1,34 +1,133 @@
+use std::process::ExitCode;
+
 use clap::Parser;
-use task::Task;
+use kmf::{
+  Error, Kmf, Task, cli,
+  config::{self, OutputFormat},
+  error::ErrorCategory,
+  event::{Event, EventReporter},
+  i18n::{self, Language},
+};
 use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 
-mod cli;
-mod config;
-mod error;
-mod kmf;
-mod resolver;
-mod task;
-mod util;
-
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
   tracing_subscriber::registry()
-    .with(tracing_subscriber::fmt::layer())
+    .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
     .with(EnvFilter::from_default_env())
     .try_init()
     .expect("tracing init failed");
 
-  let cli = cli::Cli::parse();
+  let cli = match cli::Cli::try_parse() {
+    Ok(cli) => cli,
+    Err(err) => return usage_error(err),
+  };
+
+  if matches!(
+    cli.command,
+    cli::Command::Config { .. } | cli::Command::Game { .. }
+  ) {
+    // 这两个命令用来修复配置，配置无效时也用其中的语言
+    let config = config::Config::load_from_cli(&cli).await.ok();
+    i18n::set_language(config.and_then(|x| x.language));
+  }
+  if let cli::Command::Config { command } = &cli.command {
+    let output = cli.output.unwrap_or_default();
+    let result = config::command::run(&cli, command, output)
+      .await
+      .map_err(Error::from);
+    let tasks = usize::from(result.is_ok());
+    return finish(result, output, tasks);
+  }
+  if let cli::Command::Game { command } = &cli.command {
+    let output = cli.output.unwrap_or_default();
+    let result = config::command::run_game(&cli, command, output)
+      .await
+      .map_err(Error::from);
+    let tasks = usize::from(result.is_ok());
+    return finish(result, output, tasks);
+  }
+
+  let config = match config::Config::try_from_cli(&cli).await {
+    Ok(config) => config,
+    Err(err) => return finish(Err(err.into()), cli.output.unwrap_or_default(), 0),
+  };
+  i18n::set_language(config.language);
+  let mut tasks = 0;
+  let result = run(&cli, &config, &mut tasks).await;
+  finish(result, config.output, tasks)
+}
 
-  let config = config::Config::try_from_cli(&cli)
-    .await
-    .expect("config failed");
-  let kmf = kmf::Kmf::try_from_config(&config)
-    .await
-    .expect("kmf failed");
-  let tasks = Task::from_cli(&cli);
+async fn run(cli: &cli::Cli, config: &config::Config, tasks: &mut usize) -> Result<(), Error> {
+  let kmf = Kmf::try_from_config(config).await?;
+
+  let mut evict = false;
+  for task in Task::from_cli(cli) {
+    evict |= task.fills_cache();
+    kmf.run(task).await?;
+    *tasks += 1;
+  }
+  // 只读和试运行的任务不清理缓存，也就不用等缓存锁
+  if evict {
+    kmf.evict_cache().await?;
+  }
+  Ok(())
+}
+
+/// Report invalid arguments, as an `error` event when `--output json` is among them
+fn usage_error(err: clap::Error) -> ExitCode {
+  let args = std::env::args().collect::<Vec<_>>();
+  let json = args.iter().any(|x| x == "--output=json")
+    || args
+      .windows(2)
+      .any(|x| x[0] == "--output" && x[1] == "json");
+  if !json || !err.use_stderr() {
+    err.exit();
+  }
+  let exit_code = ErrorCategory::Usage.exit_code();
+  let reporter = EventReporter::json();
+  reporter.report(Event::Error {
+    message: err.render().to_string().trim_end().to_string(),
+    category: ErrorCategory::Usage,
+    exit_code,
+    hint: None,
+  });
+  reporter.report(Event::Summary {
+    ok: false,
+    exit_code,
+    tasks: 0,
+  });
+  ExitCode::from(exit_code)
+}
 
-  for task in tasks {
-    kmf.run(task).await.expect("task failed");
+/// Report the result and turn it into exit code
+fn finish(result: Result<(), Error>, output: OutputFormat, tasks: usize) -> ExitCode {
+  let exit_code = match result.as_ref() {
+    Ok(()) => 0,
+    Err(err) => err.category().exit_code(),
+  };
+  match output {
+    OutputFormat::Human => {
+      if let Err(err) = result.as_ref() {
+        eprintln!("{}", err.report());
+      }
+    }
+    OutputFormat::Json => {
+      let reporter = EventReporter::json();
+      if let Err(err) = result.as_ref() {
+        reporter.report(Event::Error {
+          message: err.to_string(),
+          category: err.category(),
+          exit_code,
+          hint: err.hint().map(|x| x.localize(Language::En)),
+        });
+      }
+      reporter.report(Event::Summary {
+        ok: result.is_ok(),
+        exit_code,
+        tasks,
+      });
+    }
   }
+  ExitCode::from(exit_code)
 }
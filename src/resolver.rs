@@ -1,10 +1,15 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use url::Url;
 
-use crate::util::error::UnzipFileError;
+use crate::{
+  error::ErrorCategory,
+  event::EventReporter,
+  i18n::Msg,
+  util::error::{LockError, StoreError, UnzipFileError},
+};
 
 pub mod impls;
 
@@ -13,6 +18,16 @@ pub mod impls;
 pub enum Error {
   #[error("cannot resolve")]
   CannotResolve,
+  #[error("not available offline: {url}")]
+  NotAvailableOffline { url: Url },
+  #[error("digest mismatch for {url}: expected {expected}, got {actual}")]
+  DigestMismatch {
+    url: Box<Url>,
+    expected: String,
+    actual: String,
+  },
+  #[error("resolver plugin {program:?}: {message}")]
+  Plugin { program: PathBuf, message: String },
   #[error("reqwest_middleware: {0}")]
   Reqwest(#[from] reqwest_middleware::Error),
   #[error("io: {0}")]
@@ -21,31 +36,184 @@ pub enum Error {
   TomlDe(#[from] toml::de::Error),
   #[error("toml::ser: {0}")]
   TomlSer(#[from] toml::ser::Error),
+  #[error("store: {0}")]
+  Store(#[from] StoreError),
   #[error("UnzipFile: {0}")]
   UnzipFile(#[from] UnzipFileError),
   #[error("url::Parse: {0}")]
   UrlParse(#[from] url::ParseError),
 }
 
+impl Error {
+  pub fn category(&self) -> ErrorCategory {
+    match self {
+      Error::CannotResolve | Error::NotAvailableOffline { .. } => ErrorCategory::NotFound,
+      Error::DigestMismatch { .. } | Error::UnzipFile(UnzipFileError::AsyncZipError(_)) => {
+        ErrorCategory::Integrity
+      }
+      Error::Reqwest(err) => ErrorCategory::of_http(err.status()),
+      Error::Store(StoreError::Lock(LockError::Locked { .. })) => ErrorCategory::Conflict,
+      Error::Plugin { .. } => ErrorCategory::Other,
+      Error::UrlParse(_) => ErrorCategory::Config,
+      Error::Io(_)
+      | Error::TomlDe(_)
+      | Error::TomlSer(_)
+      | Error::Store(_)
+      | Error::UnzipFile(_) => ErrorCategory::Other,
+    }
+  }
+
+  /// Localized message, `None` for errors only meaningful in English
+  pub fn message(&self) -> Option<Msg<'_>> {
+    match self {
+      Error::CannotResolve => Some(Msg::CannotResolve),
+      Error::Store(StoreError::Lock(err)) => err.message(),
+      Error::NotAvailableOffline { url } => Some(Msg::NotAvailableOffline { url }),
+      Error::DigestMismatch {
+        url,
+        expected,
+        actual,
+      } => Some(Msg::DigestMismatch {
+        url,
+        expected,
+        actual,
+      }),
+      _ => None,
+    }
+  }
+
+  /// What the user may do about the error
+  pub fn hint(&self) -> Option<Msg<'static>> {
+    match self {
+      Error::CannotResolve => Some(Msg::HintCannotResolve),
+      Error::NotAvailableOffline { .. } => Some(Msg::HintOffline),
+      Error::DigestMismatch { .. } => Some(Msg::HintCorruptDownload),
+      Error::UnzipFile(UnzipFileError::AsyncZipError(_)) => Some(Msg::HintInvalidZip),
+      Error::Reqwest(err) => match ErrorCategory::of_http(err.status()) {
+        ErrorCategory::NotFound => Some(Msg::HintUrlNotFound),
+        _ => Some(Msg::HintNetwork),
+      },
+      Error::Store(StoreError::Lock(LockError::Locked { .. })) => Some(Msg::HintWait),
+      _ => None,
+    }
+  }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Info resolved
-#[allow(dead_code)]
+/// Priority of the built-in `kmf` resolver
+pub const KMF_RESOLVER_PRIORITY: i32 = 20;
+/// Priority of the built-in `web` resolver
+pub const WEB_RESOLVER_PRIORITY: i32 = 10;
+/// Names of the built-in resolvers, plugins cannot take them
+pub const BUILTIN_RESOLVERS: &[&str] = &["kmf", "web"];
+
+/// Mod resolved from its url, fetch it with [`Resolver::fetch`] without resolving again
 #[derive(Debug, Clone)]
-pub struct ResolveInfo {
+pub struct Artifact {
+  /// Id of the cache entry the artifact is cached under
   pub id: String,
+  /// Url of the mod
   pub url: Url,
-  pub last_updated: DateTime<Utc>,
+  /// Where the content is downloaded from
+  pub source: Url,
+  /// Version of the mod, if the resolver knows it
+  pub version: Option<String>,
+  /// Hex encoded sha-256 of the content, if announced by the server
+  pub digest: Option<String>,
+  pub size: Option<u64>,
+  pub content_type: Option<String>,
+  /// `Last-Modified` of the resource, if known
+  pub last_updated: Option<DateTime<Utc>>,
+  pub etag: Option<String>,
+}
+
+/// Whether `url` picks a version of the mod like `kmf:123@1.0`, only urls without a host can
+pub fn picks_version(url: &Url) -> bool {
+  url.cannot_be_a_base() && url.path().contains('@')
+}
+
+/// What a resolver supports besides resolving and fetching
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Capabilities {
+  /// Urls may pick a version of the mod
+  pub versions: bool,
+  /// Cached mods can be resolved without network
+  pub offline: bool,
+}
+
+/// Options shared by resolvers
+#[derive(Debug, Clone, Default)]
+pub struct ResolverOptions {
+  /// Answer everything from cache record, never touch network
+  pub offline: bool,
+  /// Trust cache entries validated within this duration without asking the server
+  pub cache_ttl: Option<Duration>,
+  /// Reports download progress
+  pub reporter: EventReporter,
+  /// Wait for other kmf processes using the cache records instead of failing
+  pub wait_for_lock: bool,
+}
+
+/// Entry in a resolver's download cache
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+  pub id: String,
+  pub url: Url,
+  pub path: PathBuf,
   pub size: u64,
+  pub last_updated: Option<DateTime<Utc>>,
+  pub last_used: DateTime<Utc>,
 }
 
 /// Mod resolver
-#[allow(dead_code)]
 #[async_trait]
-pub trait Resolver {
-  fn can_resolve(&self, url: Url) -> bool;
-  async fn resolve(&self, url: Url) -> Result<ResolveInfo>;
-  async fn is_up_to_date(&self, url: Url) -> Result<bool>;
-  async fn cache(&self, url: Url) -> Result<PathBuf>;
+pub trait Resolver: Send + Sync {
+  /// Short name used to select the resolver from cli
+  fn name(&self) -> &str;
+  fn capabilities(&self) -> Capabilities;
+  /// Resolvers with higher priority are tried first when several can resolve a url
+  fn priority(&self) -> i32 {
+    0
+  }
+  fn can_resolve(&self, url: &Url) -> bool;
+  /// Id of the cache entry `url` is cached under, without touching network
+  fn cache_id(&self, url: &Url) -> Result<String>;
+  /// Resolve the newest artifact of `url`, answered from cache record when still fresh
+  async fn resolve(&self, url: &Url) -> Result<Artifact>;
+  async fn is_up_to_date(&self, url: &Url) -> Result<bool>;
+  /// Cache the artifact unless the cache already matches it, returns the cached dir
+  async fn fetch(&self, artifact: &Artifact) -> Result<PathBuf>;
   async fn clear_cache(&self) -> Result<()>;
+  async fn cache_entries(&self) -> Result<Vec<CacheEntry>>;
+  async fn remove_cache(&self, id: &str) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn picks_version_only_without_host() {
+    let picks = |url: &str| picks_version(&Url::parse(url).unwrap());
+    assert!(picks("kmf:123@1.0"));
+    assert!(picks("store:foo@latest"));
+    assert!(!picks("kmf:123"));
+    assert!(!picks("https://example.com/mods/foo@1.0.zip"));
+    assert!(!picks("https://user@example.com/mods/foo.zip"));
+  }
+
+  fn status_error(status: u16) -> Error {
+    let res = http::Response::builder().status(status).body("").unwrap();
+    reqwest_middleware::Error::from(reqwest::Response::from(res).error_for_status().unwrap_err())
+      .into()
+  }
+
+  #[test]
+  fn missing_urls_are_not_found() {
+    assert_eq!(status_error(404).category(), ErrorCategory::NotFound);
+    assert_eq!(status_error(410).category(), ErrorCategory::NotFound);
+    assert_eq!(status_error(503).category(), ErrorCategory::Network);
+    assert_eq!(status_error(403).category(), ErrorCategory::Network);
+  }
 }
@@ -1,2 +1,3 @@
 pub mod kmf;
+pub mod plugin;
 pub mod web;
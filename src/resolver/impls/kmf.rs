@@ -1,21 +1,18 @@
 use std::path::PathBuf;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
 use tracing::debug;
 use url::Url;
 
-use crate::resolver::{Error, ResolveInfo, Resolver, Result};
+use crate::resolver::{
+  Artifact, CacheEntry, Capabilities, Error, KMF_RESOLVER_PRIORITY, Resolver, ResolverOptions,
+  Result,
+};
 
 use super::web::WebResolver;
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct CacheRecord {
-  url: Url,
-  web_url: Url,
-  last_updated: DateTime<Utc>,
-}
+/// Base url of the kmf station
+pub const STATION_URL: &str = "https://kmf-station.zice.top/";
 
 pub struct KmfResolver {
   station_url_base: Url,
@@ -23,14 +20,20 @@ pub struct KmfResolver {
 }
 
 impl KmfResolver {
-  pub async fn new(cache_dir: PathBuf) -> Result<Self> {
+  pub async fn new(
+    cache_dir: PathBuf,
+    reqwest_client: reqwest_middleware::ClientWithMiddleware,
+    options: ResolverOptions,
+  ) -> Result<Self> {
     Ok(Self {
-      station_url_base: Url::parse("https://kmf-station.zice.top/").expect("it should be ok"),
-      inner: WebResolver::new(cache_dir).await?,
+      station_url_base: Url::parse(STATION_URL).expect("it should be ok"),
+      inner: WebResolver::new(cache_dir, reqwest_client, options)
+        .await?
+        .indirect_host(),
     })
   }
 
-  fn extract_url(url: Url) -> Option<(String, String)> {
+  fn extract_url(url: &Url) -> Option<(String, String)> {
     if matches!(url.scheme(), "kmf") {
       let (modid, version) = url
         .path()
@@ -44,97 +47,104 @@ impl KmfResolver {
 }
 
 impl KmfResolver {
-  fn translate_url_to_web(&self, url: Url) -> Result<Url> {
-    if !self.can_resolve(url.to_owned()) {
+  fn translate_url_to_web(&self, url: &Url) -> Result<Url> {
+    let Some((modid, version)) = Self::extract_url(url) else {
       return Err(Error::CannotResolve);
-    }
-    let (modid, version) = url
-      .path()
-      .split_once('@')
-      .unwrap_or_else(|| (url.path(), "latest"));
+    };
     Ok(
       self
         .station_url_base
         .join("mod/")?
         .join(format!("{}/", modid).as_str())?
-        .join(version)?,
+        .join(version.as_str())?,
     )
   }
 
-  pub fn can_resolve(&self, url: Url) -> bool {
+  pub fn can_resolve(&self, url: &Url) -> bool {
     matches!(url.scheme(), "kmf")
   }
 
-  pub async fn resolve(&self, url: Url) -> Result<ResolveInfo> {
-    if !self.can_resolve(url.to_owned()) {
+  pub fn cache_id(&self, url: &Url) -> Result<String> {
+    self.inner.cache_id(&self.translate_url_to_web(url)?)
+  }
+
+  pub async fn resolve(&self, url: &Url) -> Result<Artifact> {
+    let Some((modid, version)) = Self::extract_url(url) else {
       return Err(Error::CannotResolve);
-    }
-    let (modid, _version) = Self::extract_url(url.to_owned()).expect("it should be ok");
+    };
+    debug!("modid, version: {}, {}", modid, version);
 
-    let web_url = self.translate_url_to_web(url.to_owned())?;
-    let web_resolve_info = self.inner.resolve(web_url).await?;
+    let web_url = self.translate_url_to_web(url)?;
+    debug!("URL: {}", web_url);
+    let web_artifact = self.inner.resolve(&web_url).await?;
 
-    Ok(ResolveInfo {
-      id: modid.to_owned(),
+    Ok(Artifact {
       url: url.to_owned(),
-      last_updated: web_resolve_info.last_updated,
-      size: web_resolve_info.size,
+      version: Some(version),
+      ..web_artifact
     })
   }
 
-  pub async fn is_up_to_date(&self, url: Url) -> Result<bool> {
-    if !self.can_resolve(url.to_owned()) {
-      return Err(Error::CannotResolve);
-    }
-    let (modid, version) = Self::extract_url(url.to_owned()).expect("it should be ok");
-
+  pub async fn is_up_to_date(&self, url: &Url) -> Result<bool> {
     self
       .inner
-      .is_up_to_date(
-        self
-          .station_url_base
-          .join("mod")?
-          .join(modid.as_str())?
-          .join(version.as_str())?,
-      )
+      .is_up_to_date(&self.translate_url_to_web(url)?)
       .await
   }
 
-  pub async fn cache(&self, url: Url) -> Result<PathBuf> {
-    if !self.can_resolve(url.to_owned()) {
-      return Err(Error::CannotResolve);
-    }
-    let (modid, version) = Self::extract_url(url.to_owned()).expect("it should be ok");
-
-    debug!("modid, version: {}, {}", modid, version);
-
-    let web_url = self.translate_url_to_web(url.to_owned())?;
-
-    debug!("URL: {}", web_url);
-
-    self.inner.cache(web_url).await
+  pub async fn fetch(&self, artifact: &Artifact) -> Result<PathBuf> {
+    self.inner.fetch(artifact).await
   }
 
   pub async fn clear_cache(&self) -> Result<()> {
     self.inner.clear_cache().await
   }
+
+  pub async fn cache_entries(&self) -> Result<Vec<CacheEntry>> {
+    self.inner.cache_entries().await
+  }
+
+  pub async fn remove_cache(&self, id: &str) -> Result<()> {
+    self.inner.remove_cache(id).await
+  }
 }
 
 #[async_trait]
 impl Resolver for KmfResolver {
-  fn can_resolve(&self, url: Url) -> bool {
+  fn name(&self) -> &str {
+    "kmf"
+  }
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      versions: true,
+      offline: true,
+    }
+  }
+  fn priority(&self) -> i32 {
+    KMF_RESOLVER_PRIORITY
+  }
+  fn can_resolve(&self, url: &Url) -> bool {
     self.can_resolve(url)
   }
-  async fn resolve(&self, url: Url) -> Result<ResolveInfo> {
+  fn cache_id(&self, url: &Url) -> Result<String> {
+    self.cache_id(url)
+  }
+  async fn resolve(&self, url: &Url) -> Result<Artifact> {
     self.resolve(url).await
   }
-  async fn is_up_to_date(&self, url: Url) -> Result<bool> {
+  async fn is_up_to_date(&self, url: &Url) -> Result<bool> {
     self.is_up_to_date(url).await
   }
-  async fn cache(&self, url: Url) -> Result<PathBuf> {
-    self.cache(url).await
+  async fn fetch(&self, artifact: &Artifact) -> Result<PathBuf> {
+    self.fetch(artifact).await
   }
   async fn clear_cache(&self) -> Result<()> {
     self.clear_cache().await
   }
+  async fn cache_entries(&self) -> Result<Vec<CacheEntry>> {
+    self.cache_entries().await
+  }
+  async fn remove_cache(&self, id: &str) -> Result<()> {
+    self.remove_cache(id).await
+  }
 }
@@ -0,0 +1,570 @@
+//! Resolvers provided by external executables, `kmf-resolver-<scheme>` on `PATH` or configured
+//! in `[resolvers]`. kmf starts the executable on first use and sends it JSON-lines requests on
+//! stdin, see "Resolver plugins" in the README for the protocol.
+
+use std::{
+  collections::BTreeMap,
+  path::PathBuf,
+  process::Stdio,
+  sync::atomic::{AtomicU64, Ordering},
+  time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use redb::{ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::{
+  fs::{self, File},
+  io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+  process::{Child, ChildStdin, ChildStdout, Command},
+  sync::{Mutex, OnceCell},
+};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{
+  resolver::{Artifact, CacheEntry, Capabilities, Error, Resolver, ResolverOptions, Result},
+  util::{dir_size, empty_dir, ensure_dir, file_sha256, store::Store, unzip_file},
+};
+
+/// Executables named `kmf-resolver-<scheme>` are resolver plugins
+pub const PLUGIN_PREFIX: &str = "kmf-resolver-";
+
+/// Cache records by cache id
+const CACHE_RECORDS: TableDefinition<&str, &[u8]> = TableDefinition::new("cache_records");
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CacheRecord {
+  url: Url,
+  source: Url,
+  version: Option<String>,
+  digest: Option<String>,
+  content_length: Option<u64>,
+  content_type: Option<String>,
+  /// When the cache was last confirmed to be the newest
+  validated_at: DateTime<Utc>,
+  /// Size of the extracted cache dir
+  size: u64,
+  last_used: DateTime<Utc>,
+}
+
+impl CacheRecord {
+  /// Whether the cache is the artifact, judged by digest or else version
+  fn matches(&self, artifact: &Artifact) -> bool {
+    if let (Some(x), Some(y)) = (self.digest.as_ref(), artifact.digest.as_ref()) {
+      return x == y;
+    }
+    if let (Some(x), Some(y)) = (self.version.as_ref(), artifact.version.as_ref()) {
+      return x == y;
+    }
+    false
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+  id: u64,
+  #[serde(default)]
+  result: serde_json::Value,
+  error: Option<ResponseError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseError {
+  message: String,
+}
+
+/// Result of `resolve`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Resolved {
+  source: Option<Url>,
+  version: Option<String>,
+  digest: Option<String>,
+  size: Option<u64>,
+  content_type: Option<String>,
+}
+
+/// Running plugin
+struct Process {
+  // 保留子进程，丢弃时结束它
+  _child: Child,
+  stdin: ChildStdin,
+  stdout: Lines<BufReader<ChildStdout>>,
+}
+
+/// Plugins configured or found on `PATH`, by scheme
+pub fn discover(configured: &BTreeMap<String, PathBuf>) -> BTreeMap<String, PathBuf> {
+  let mut plugins = configured.to_owned();
+  let Some(paths) = std::env::var_os("PATH") else {
+    return plugins;
+  };
+  for dir in std::env::split_paths(&paths) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+      continue;
+    };
+    for entry in entries.flatten() {
+      let path = entry.path();
+      let Some(scheme) = path
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .and_then(|x| x.strip_prefix(PLUGIN_PREFIX))
+      else {
+        continue;
+      };
+      if plugins.contains_key(scheme) {
+        continue;
+      }
+      // 取 PATH 中第一个可执行的同名文件
+      if let Ok(program) = which::which(format!("{PLUGIN_PREFIX}{scheme}")) {
+        plugins.insert(scheme.to_string(), program);
+      }
+    }
+  }
+  plugins
+}
+
+pub struct PluginResolver {
+  scheme: String,
+  program: PathBuf,
+  store: Store,
+  download_cache_dir: PathBuf,
+  partial_download_dir: PathBuf,
+  options: ResolverOptions,
+  /// How long to wait for each response
+  timeout: Duration,
+  process: Mutex<Option<Process>>,
+  next_id: AtomicU64,
+  tables_created: OnceCell<()>,
+}
+
+impl PluginResolver {
+  pub async fn new(
+    scheme: String,
+    program: PathBuf,
+    cache_dir: PathBuf,
+    options: ResolverOptions,
+    timeout: Duration,
+  ) -> Result<Self> {
+    let plugin_resolver = Self {
+      scheme,
+      program,
+      store: Store::new(
+        ensure_dir(cache_dir.as_path()).await?.join("record.redb"),
+        options.wait_for_lock,
+      ),
+      download_cache_dir: ensure_dir(cache_dir.join("download").as_path())
+        .await?
+        .to_path_buf(),
+      partial_download_dir: ensure_dir(cache_dir.join("partial").as_path())
+        .await?
+        .to_path_buf(),
+      options,
+      timeout,
+      process: Mutex::new(None),
+      next_id: AtomicU64::new(1),
+      tables_created: OnceCell::new(),
+    };
+    Ok(plugin_resolver)
+  }
+
+  /// The store, its tables created on first use so a corrupt store only fails the tasks reading it
+  async fn store(&self) -> Result<&Store> {
+    self
+      .tables_created
+      .get_or_try_init(|| {
+        self.store.write(|transaction| {
+          transaction.open_table(CACHE_RECORDS)?;
+          Ok(())
+        })
+      })
+      .await?;
+    Ok(&self.store)
+  }
+
+  fn error(&self, message: impl ToString) -> Error {
+    Error::Plugin {
+      program: self.program.to_owned(),
+      message: message.to_string(),
+    }
+  }
+
+  fn spawn(&self) -> Result<Process> {
+    debug!("spawn resolver plugin {:?}", self.program);
+    let mut child = Command::new(self.program.as_path())
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::inherit())
+      .kill_on_drop(true)
+      .spawn()
+      .map_err(|err| self.error(format!("cannot start: {err}")))?;
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+    Ok(Process {
+      _child: child,
+      stdin,
+      stdout: BufReader::new(stdout).lines(),
+    })
+  }
+
+  /// Send a request and wait for its response
+  async fn call<T: DeserializeOwned>(&self, method: &str, params: serde_json::Value) -> Result<T> {
+    let mut process = self.process.lock().await;
+    if process.is_none() {
+      *process = Some(self.spawn()?);
+    }
+    let running = process.as_mut().expect("process is spawned");
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let request = json!({ "id": id, "method": method, "params": params });
+    debug!("resolver plugin request: {}", request);
+
+    let result = tokio::time::timeout(self.timeout, async {
+      running
+        .stdin
+        .write_all(format!("{request}\n").as_bytes())
+        .await?;
+      running.stdin.flush().await?;
+      loop {
+        let Some(line) = running.stdout.next_line().await? else {
+          return Err(self.error("exited without a response"));
+        };
+        let response = serde_json::from_str::<Response>(line.as_str())
+          .map_err(|err| self.error(format!("invalid response {line:?}: {err}")))?;
+        if response.id == id {
+          break Ok(response);
+        }
+        warn!("ignore response to unknown request {}", response.id);
+      }
+    })
+    .await
+    .unwrap_or_else(|_| {
+      Err(self.error(format!(
+        "no response to {method} within {}s",
+        self.timeout.as_secs()
+      )))
+    });
+    let response = match result {
+      Ok(response) => response,
+      Err(err) => {
+        // 进程状态未知，下次请求时重新启动
+        *process = None;
+        return Err(err);
+      }
+    };
+    if let Some(error) = response.error {
+      return Err(self.error(error.message));
+    }
+    serde_json::from_value(response.result)
+      .map_err(|err| self.error(format!("invalid result of {method}: {err}")))
+  }
+
+  async fn get_cache_record(&self, id: &str) -> Result<Option<CacheRecord>> {
+    let id = id.to_string();
+    Ok(
+      self
+        .store()
+        .await?
+        .read(move |transaction| {
+          let records = transaction.open_table(CACHE_RECORDS)?;
+          Ok(
+            records
+              .get(id.as_str())?
+              .map(|x| serde_json::from_slice(x.value()))
+              .transpose()?,
+          )
+        })
+        .await?,
+    )
+  }
+
+  async fn list_cache_records(&self) -> Result<Vec<(String, CacheRecord)>> {
+    Ok(
+      self
+        .store()
+        .await?
+        .read(|transaction| {
+          let records = transaction.open_table(CACHE_RECORDS)?;
+          let mut cache_records = Vec::new();
+          for entry in records.iter()? {
+            let (id, value) = entry?;
+            cache_records.push((
+              id.value().to_string(),
+              serde_json::from_slice(value.value())?,
+            ));
+          }
+          Ok(cache_records)
+        })
+        .await?,
+    )
+  }
+
+  async fn put_cache_record(&self, id: &str, cache_record: &CacheRecord) -> Result<()> {
+    let (id, cache_record) = (id.to_string(), cache_record.to_owned());
+    Ok(
+      self
+        .store()
+        .await?
+        .write(move |transaction| {
+          let mut records = transaction.open_table(CACHE_RECORDS)?;
+          records.insert(id.as_str(), serde_json::to_vec(&cache_record)?.as_slice())?;
+          Ok(())
+        })
+        .await?,
+    )
+  }
+
+  /// Remove records of `id`, or every record when `None`
+  async fn remove_cache_records(&self, id: Option<&str>) -> Result<()> {
+    let id = id.map(ToString::to_string);
+    Ok(
+      self
+        .store()
+        .await?
+        .write(move |transaction| {
+          let mut records = transaction.open_table(CACHE_RECORDS)?;
+          match id {
+            Some(id) => {
+              records.remove(id.as_str())?;
+            }
+            None => records.retain(|_, _| false)?,
+          }
+          Ok(())
+        })
+        .await?,
+    )
+  }
+
+  async fn remove_partial(&self, id: &str) -> Result<()> {
+    match fs::remove_file(self.partial_download_dir.join(format!("{}.part", id))).await {
+      Ok(_) => Ok(()),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  /// Whether the record was validated recently enough to be trusted without asking the plugin
+  fn is_within_ttl(&self, cache_record: &CacheRecord) -> bool {
+    self.options.cache_ttl.is_some_and(|ttl| {
+      (Utc::now() - cache_record.validated_at)
+        .to_std()
+        .is_ok_and(|x| x < ttl)
+    })
+  }
+}
+
+impl PluginResolver {
+  pub fn can_resolve(&self, url: &Url) -> bool {
+    url.scheme() == self.scheme
+  }
+
+  pub fn cache_id(&self, url: &Url) -> Result<String> {
+    if !self.can_resolve(url) {
+      return Err(Error::CannotResolve);
+    }
+    Ok(hex::encode(
+      Sha256::digest(url.as_str().as_bytes()).to_vec().as_slice(),
+    ))
+  }
+
+  pub async fn resolve(&self, url: &Url) -> Result<Artifact> {
+    let id = self.cache_id(url)?;
+
+    if let Some(cache_record) = self.get_cache_record(id.as_str()).await?
+      && (self.options.offline || self.is_within_ttl(&cache_record))
+    {
+      return Ok(Artifact {
+        id,
+        url: url.to_owned(),
+        source: cache_record.source,
+        version: cache_record.version,
+        digest: cache_record.digest,
+        size: cache_record.content_length,
+        content_type: cache_record.content_type,
+        last_updated: None,
+        etag: None,
+      });
+    }
+    if self.options.offline {
+      return Err(Error::NotAvailableOffline {
+        url: url.to_owned(),
+      });
+    }
+
+    let resolved = self
+      .call::<Resolved>("resolve", json!({ "url": url }))
+      .await?;
+    Ok(Artifact {
+      id,
+      url: url.to_owned(),
+      source: resolved.source.unwrap_or_else(|| url.to_owned()),
+      version: resolved.version,
+      digest: resolved.digest.map(|x| x.to_ascii_lowercase()),
+      size: resolved.size,
+      content_type: resolved.content_type,
+      last_updated: None,
+      etag: None,
+    })
+  }
+
+  pub async fn is_up_to_date(&self, url: &Url) -> Result<bool> {
+    let Some(cache_record) = self.get_cache_record(self.cache_id(url)?.as_str()).await? else {
+      return Ok(false);
+    };
+    if self.options.offline || self.is_within_ttl(&cache_record) {
+      return Ok(true);
+    }
+    self
+      .call(
+        "is_up_to_date",
+        json!({
+          "url": url,
+          "version": cache_record.version,
+          "digest": cache_record.digest,
+        }),
+      )
+      .await
+  }
+
+  pub async fn fetch(&self, artifact: &Artifact) -> Result<PathBuf> {
+    let id = artifact.id.as_str();
+    let cache_dir = self.download_cache_dir.join(id);
+    if fs::try_exists(cache_dir.as_path()).await?
+      && let Some(mut cache_record) = self.get_cache_record(id).await?
+      && (self.options.offline
+        || self.is_within_ttl(&cache_record)
+        || cache_record.matches(artifact))
+    {
+      debug!("reuse current cache: {:?}", cache_dir);
+      cache_record.validated_at = Utc::now();
+      cache_record.last_used = Utc::now();
+      self.put_cache_record(id, &cache_record).await?;
+      return Ok(cache_dir);
+    }
+    if self.options.offline {
+      return Err(Error::NotAvailableOffline {
+        url: artifact.url.to_owned(),
+      });
+    }
+
+    let partial_file = self.partial_download_dir.join(format!("{}.part", id));
+    self
+      .call::<serde_json::Value>(
+        "fetch",
+        json!({
+          "url": artifact.url,
+          "source": artifact.source,
+          "version": artifact.version,
+          "path": partial_file,
+        }),
+      )
+      .await?;
+    if let Some(expected) = artifact.digest.as_ref() {
+      let actual = file_sha256(partial_file.as_path()).await?;
+      if &actual != expected {
+        self.remove_partial(id).await?;
+        return Err(Error::DigestMismatch {
+          url: Box::new(artifact.url.to_owned()),
+          expected: expected.to_owned(),
+          actual,
+        });
+      }
+    }
+    empty_dir(cache_dir.as_path()).await?;
+    debug!("unzip {:?} -> {:?}", partial_file, cache_dir);
+    unzip_file(
+      File::open(partial_file.as_path()).await?,
+      cache_dir.as_path(),
+    )
+    .await?;
+    self.remove_partial(id).await?;
+    let cache_record = CacheRecord {
+      url: artifact.url.to_owned(),
+      source: artifact.source.to_owned(),
+      version: artifact.version.to_owned(),
+      digest: artifact.digest.to_owned(),
+      content_length: artifact.size,
+      content_type: artifact.content_type.to_owned(),
+      validated_at: Utc::now(),
+      size: dir_size(cache_dir.to_owned()).await?,
+      last_used: Utc::now(),
+    };
+    self.put_cache_record(id, &cache_record).await?;
+    Ok(cache_dir)
+  }
+
+  pub async fn clear_cache(&self) -> Result<()> {
+    empty_dir(self.download_cache_dir.as_path()).await?;
+    empty_dir(self.partial_download_dir.as_path()).await?;
+    self.remove_cache_records(None).await?;
+    Ok(())
+  }
+
+  pub async fn cache_entries(&self) -> Result<Vec<CacheEntry>> {
+    let mut entries = self
+      .list_cache_records()
+      .await?
+      .into_iter()
+      .map(|(id, cache_record)| CacheEntry {
+        path: self.download_cache_dir.join(id.as_str()),
+        id,
+        url: cache_record.url,
+        size: cache_record.size,
+        last_updated: None,
+        last_used: cache_record.last_used,
+      })
+      .collect::<Vec<_>>();
+    entries.sort_by_key(|x| std::cmp::Reverse(x.last_used));
+    Ok(entries)
+  }
+
+  pub async fn remove_cache(&self, id: &str) -> Result<()> {
+    let cache_dir = self.download_cache_dir.join(id);
+    if fs::try_exists(cache_dir.as_path()).await? {
+      fs::remove_dir_all(cache_dir).await?;
+    }
+    self.remove_partial(id).await?;
+    self.remove_cache_records(Some(id)).await?;
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl Resolver for PluginResolver {
+  fn name(&self) -> &str {
+    self.scheme.as_str()
+  }
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      // 插件自己解析地址中的版本
+      versions: true,
+      offline: true,
+    }
+  }
+  fn can_resolve(&self, url: &Url) -> bool {
+    self.can_resolve(url)
+  }
+  fn cache_id(&self, url: &Url) -> Result<String> {
+    self.cache_id(url)
+  }
+  async fn resolve(&self, url: &Url) -> Result<Artifact> {
+    self.resolve(url).await
+  }
+  async fn is_up_to_date(&self, url: &Url) -> Result<bool> {
+    self.is_up_to_date(url).await
+  }
+  async fn fetch(&self, artifact: &Artifact) -> Result<PathBuf> {
+    self.fetch(artifact).await
+  }
+  async fn clear_cache(&self) -> Result<()> {
+    self.clear_cache().await
+  }
+  async fn cache_entries(&self) -> Result<Vec<CacheEntry>> {
+    self.cache_entries().await
+  }
+  async fn remove_cache(&self, id: &str) -> Result<()> {
+    self.remove_cache(id).await
+  }
+}
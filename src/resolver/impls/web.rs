@@ -1,192 +1,807 @@
 use std::{collections::HashMap, path::PathBuf, time::SystemTime};
 
 use crate::{
-  resolver::{Error, ResolveInfo, Result},
-  util::{empty_dir, ensure_dir, ensure_file, unzip_file},
+  event::Event,
+  resolver::{
+    Artifact, CacheEntry, Capabilities, Error, Resolver, ResolverOptions, Result,
+    WEB_RESOLVER_PRIORITY,
+  },
+  util::{
+    dir_size, empty_dir, ensure_dir, file_sha256, reqwest::middleware::IndirectHost, store::Store,
+    unzip_file,
+  },
 };
 use async_trait::async_trait;
+use base64::{Engine, prelude::BASE64_STANDARD};
 use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
-use headers::{ContentLength, HeaderMapExt, LastModified};
-use http_cache_reqwest::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
-use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
-use reqwest_tracing::TracingMiddleware;
+use headers::{ContentLength, ContentRange, HeaderMapExt, LastModified};
+use http::{
+  HeaderMap, Method, StatusCode,
+  header::{CONTENT_TYPE, ETAG, HeaderName, IF_RANGE, LAST_MODIFIED, RANGE},
+};
+use http_cache_reqwest::CacheMode;
+use redb::{ReadableTable, TableDefinition};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use tokio::{
   fs::{self, File},
-  io,
+  io::AsyncWriteExt,
+  sync::OnceCell,
 };
-use tokio_util::io::StreamReader;
-use tracing::debug;
+use tracing::{debug, info, warn};
 use url::Url;
 
-use crate::resolver::Resolver;
+/// Cache records by cache id
+const CACHE_RECORDS: TableDefinition<&str, &[u8]> = TableDefinition::new("cache_records");
+/// Source url -> cache id
+const CACHE_RECORDS_BY_URL: TableDefinition<&str, &str> =
+  TableDefinition::new("cache_records_by_url");
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CacheRecord {
   url: Url,
-  last_updated: DateTime<Utc>,
+  last_updated: Option<DateTime<Utc>>,
+  etag: Option<String>,
+  content_length: Option<u64>,
+  /// Hex encoded sha-256 of the downloaded file, if announced by the server
+  digest: Option<String>,
+  #[serde(default)]
+  version: Option<String>,
+  #[serde(default)]
+  content_type: Option<String>,
+  /// When the cache was last confirmed to match the server
+  #[serde(default)]
+  validated_at: DateTime<Utc>,
+  /// Size of the extracted cache dir
+  #[serde(default)]
+  size: u64,
+  #[serde(default)]
+  last_used: DateTime<Utc>,
 }
 
-impl From<ResolveInfo> for CacheRecord {
-  fn from(value: ResolveInfo) -> Self {
+impl From<&Artifact> for CacheRecord {
+  fn from(value: &Artifact) -> Self {
     Self {
       last_updated: value.last_updated,
-      url: value.url,
+      etag: value.etag.to_owned(),
+      content_length: value.size,
+      digest: value.digest.to_owned(),
+      version: value.version.to_owned(),
+      content_type: value.content_type.to_owned(),
+      url: value.source.to_owned(),
+      validated_at: Utc::now(),
+      size: 0,
+      last_used: Utc::now(),
     }
   }
 }
 
+impl CacheRecord {
+  /// Whether the cache still matches `latest`, judged by the strongest
+  /// validator known on both sides. Unknown freshness counts as stale.
+  fn matches(&self, latest: &Artifact) -> bool {
+    if let (Some(x), Some(y)) = (self.digest.as_ref(), latest.digest.as_ref()) {
+      return x == y;
+    }
+    if let (Some(x), Some(y)) = (self.etag.as_ref(), latest.etag.as_ref()) {
+      return x == y;
+    }
+    if let (Some(x), Some(y)) = (self.last_updated, latest.last_updated) {
+      return x == y
+        && self
+          .content_length
+          .zip(latest.size)
+          .is_none_or(|(x, y)| x == y);
+    }
+    false
+  }
+}
+
+/// Sha-256 announced in `Repr-Digest`, `Content-Digest` or the legacy `Digest` header, hex encoded.
+/// `Content-Digest` of a `206` only covers the returned range, so it is ignored there
+fn parse_digest(status: StatusCode, headers: &HeaderMap) -> Option<String> {
+  ["repr-digest", "content-digest", "digest"]
+    .into_iter()
+    .filter(|name| status != StatusCode::PARTIAL_CONTENT || *name != "content-digest")
+    .filter_map(|name| headers.get(name).and_then(|x| x.to_str().ok()))
+    .flat_map(|value| value.split(','))
+    .filter_map(|item| item.trim().split_once('='))
+    .filter(|(algorithm, _)| algorithm.eq_ignore_ascii_case("sha-256"))
+    .find_map(|(_, digest)| BASE64_STANDARD.decode(digest.trim().trim_matches(':')).ok())
+    .map(hex::encode)
+}
+
+/// Validator of a partially downloaded file, used to resume it with `If-Range`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PartialRecord {
+  url: Url,
+  etag: Option<String>,
+  last_modified: Option<String>,
+}
+
+impl PartialRecord {
+  /// `If-Range` only accepts strong ETags, otherwise fall back to `Last-Modified`.
+  fn if_range(&self) -> Option<&str> {
+    self
+      .etag
+      .as_deref()
+      .filter(|x| !x.starts_with("W/"))
+      .or(self.last_modified.as_deref())
+  }
+}
+
 pub struct WebResolver {
-  cache_record_file: PathBuf,
+  store: Store,
   download_cache_dir: PathBuf,
+  partial_download_dir: PathBuf,
   reqwest_client: reqwest_middleware::ClientWithMiddleware,
+  options: ResolverOptions,
+  /// Urls are not named by the user, see [`IndirectHost`]
+  indirect_host: bool,
+  /// `record.toml` used before the store
+  legacy_record_file: PathBuf,
+  migrated: OnceCell<()>,
 }
 
 impl WebResolver {
-  pub async fn new(cache_dir: PathBuf) -> Result<Self> {
-    Ok(Self {
-      cache_record_file: ensure_file(cache_dir.join("record.toml").as_path())
+  pub async fn new(
+    cache_dir: PathBuf,
+    reqwest_client: reqwest_middleware::ClientWithMiddleware,
+    options: ResolverOptions,
+  ) -> Result<Self> {
+    let web_resolver = Self {
+      store: Store::new(
+        ensure_dir(cache_dir.as_path()).await?.join("record.redb"),
+        options.wait_for_lock,
+      ),
+      download_cache_dir: ensure_dir(cache_dir.join("download").as_path())
         .await?
         .to_path_buf(),
-      download_cache_dir: ensure_dir(cache_dir.join("download").as_path())
+      partial_download_dir: ensure_dir(cache_dir.join("partial").as_path())
         .await?
         .to_path_buf(),
-      reqwest_client: reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
-        .with(TracingMiddleware::default())
-        .with(RetryTransientMiddleware::new_with_policy(
-          ExponentialBackoff::builder().build_with_max_retries(3),
-        ))
-        .with(Cache(HttpCache {
-          mode: CacheMode::Default,
-          manager: CACacheManager {
-            path: ensure_dir(cache_dir.join("http_ca").as_path())
-              .await?
-              .to_path_buf(),
-          },
-          options: HttpCacheOptions::default(),
-        }))
-        .build(),
-    })
+      reqwest_client,
+      options,
+      indirect_host: false,
+      legacy_record_file: cache_dir.join("record.toml"),
+      migrated: OnceCell::new(),
+    };
+    Ok(web_resolver)
+  }
+
+  /// Urls given to the resolver are made up by kmf rather than named by the user, so they
+  /// never get the netrc `default` credentials
+  pub fn indirect_host(mut self) -> Self {
+    self.indirect_host = true;
+    self
+  }
+
+  fn request(&self, method: Method, url: &Url) -> reqwest_middleware::RequestBuilder {
+    let req = self.reqwest_client.request(method, url.to_owned());
+    if self.indirect_host {
+      req.with_extension(IndirectHost)
+    } else {
+      req
+    }
   }
 }
 
 impl WebResolver {
-  async fn read_cache_record(&self) -> Result<HashMap<String, CacheRecord>> {
-    Ok(toml::from_str(
-      fs::read_to_string(self.cache_record_file.as_path())
+  /// Move records from `record.toml` used before the store, creating the tables on the way
+  async fn migrate_cache_record(&self) -> Result<()> {
+    let legacy_file = self.legacy_record_file.to_owned();
+    let legacy_cache_records = if fs::try_exists(legacy_file.as_path()).await? {
+      toml::from_str::<HashMap<String, CacheRecord>>(
+        fs::read_to_string(legacy_file.as_path()).await?.as_str(),
+      )
+      .unwrap_or_else(|err| {
+        warn!("ignore corrupt {:?}: {}", legacy_file, err);
+        HashMap::new()
+      })
+    } else {
+      HashMap::new()
+    };
+    let migrated = !legacy_cache_records.is_empty();
+    self
+      .store
+      .write(move |transaction| {
+        let mut records = transaction.open_table(CACHE_RECORDS)?;
+        let mut records_by_url = transaction.open_table(CACHE_RECORDS_BY_URL)?;
+        for (id, cache_record) in legacy_cache_records.iter() {
+          records.insert(id.as_str(), serde_json::to_vec(cache_record)?.as_slice())?;
+          records_by_url.insert(cache_record.url.as_str(), id.as_str())?;
+        }
+        Ok(())
+      })
+      .await?;
+    if migrated {
+      info!("migrated {:?} into store", legacy_file);
+    }
+    match fs::remove_file(legacy_file).await {
+      Ok(_) => {}
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+      Err(err) => return Err(err.into()),
+    }
+    Ok(())
+  }
+
+  /// The store, migrated on first use so a corrupt store only fails the tasks reading it
+  async fn store(&self) -> Result<&Store> {
+    self
+      .migrated
+      .get_or_try_init(|| self.migrate_cache_record())
+      .await?;
+    Ok(&self.store)
+  }
+
+  async fn get_cache_record(&self, id: &str) -> Result<Option<CacheRecord>> {
+    let id = id.to_string();
+    Ok(
+      self
+        .store()
         .await?
-        .as_str(),
-    )?)
+        .read(move |transaction| {
+          let records = transaction.open_table(CACHE_RECORDS)?;
+          Ok(
+            records
+              .get(id.as_str())?
+              .map(|x| serde_json::from_slice(x.value()))
+              .transpose()?,
+          )
+        })
+        .await?,
+    )
+  }
+
+  async fn find_cache_record_by_url(&self, url: &Url) -> Result<Option<CacheRecord>> {
+    let url = url.to_owned();
+    Ok(
+      self
+        .store()
+        .await?
+        .read(move |transaction| {
+          let records = transaction.open_table(CACHE_RECORDS)?;
+          let records_by_url = transaction.open_table(CACHE_RECORDS_BY_URL)?;
+          let Some(id) = records_by_url.get(url.as_str())? else {
+            return Ok(None);
+          };
+          Ok(
+            records
+              .get(id.value())?
+              .map(|x| serde_json::from_slice(x.value()))
+              .transpose()?,
+          )
+        })
+        .await?,
+    )
   }
 
-  async fn write_cache_record(&self, cache_record: &HashMap<String, CacheRecord>) -> Result<()> {
+  async fn list_cache_records(&self) -> Result<Vec<(String, CacheRecord)>> {
+    Ok(
+      self
+        .store()
+        .await?
+        .read(|transaction| {
+          let records = transaction.open_table(CACHE_RECORDS)?;
+          let mut cache_records = Vec::new();
+          for entry in records.iter()? {
+            let (id, value) = entry?;
+            cache_records.push((
+              id.value().to_string(),
+              serde_json::from_slice(value.value())?,
+            ));
+          }
+          Ok(cache_records)
+        })
+        .await?,
+    )
+  }
+
+  async fn put_cache_record(&self, id: &str, cache_record: &CacheRecord) -> Result<()> {
+    let (id, cache_record) = (id.to_string(), cache_record.to_owned());
+    Ok(
+      self
+        .store()
+        .await?
+        .write(move |transaction| {
+          let mut records = transaction.open_table(CACHE_RECORDS)?;
+          let mut records_by_url = transaction.open_table(CACHE_RECORDS_BY_URL)?;
+          records.insert(id.as_str(), serde_json::to_vec(&cache_record)?.as_slice())?;
+          records_by_url.insert(cache_record.url.as_str(), id.as_str())?;
+          Ok(())
+        })
+        .await?,
+    )
+  }
+
+  /// Remove records of `id`, or every record when `None`
+  async fn remove_cache_records(&self, id: Option<&str>) -> Result<()> {
+    let id = id.map(ToString::to_string);
+    Ok(
+      self
+        .store()
+        .await?
+        .write(move |transaction| {
+          let mut records = transaction.open_table(CACHE_RECORDS)?;
+          let mut records_by_url = transaction.open_table(CACHE_RECORDS_BY_URL)?;
+          match id {
+            Some(id) => {
+              if let Some(value) = records.remove(id.as_str())? {
+                let cache_record = serde_json::from_slice::<CacheRecord>(value.value())?;
+                records_by_url.remove(cache_record.url.as_str())?;
+              }
+            }
+            None => {
+              records.retain(|_, _| false)?;
+              records_by_url.retain(|_, _| false)?;
+            }
+          }
+          Ok(())
+        })
+        .await?,
+    )
+  }
+
+  /// Whether the record was validated recently enough to be trusted without asking the server
+  fn is_within_ttl(&self, cache_record: &CacheRecord) -> bool {
+    self.options.cache_ttl.is_some_and(|ttl| {
+      (Utc::now() - cache_record.validated_at)
+        .to_std()
+        .is_ok_and(|x| x < ttl)
+    })
+  }
+}
+
+impl WebResolver {
+  async fn read_partial_record(&self, id: &str) -> Result<Option<PartialRecord>> {
+    let partial_record_file = self.partial_download_dir.join(format!("{}.toml", id));
+    if !fs::try_exists(partial_record_file.as_path()).await? {
+      return Ok(None);
+    }
+    // 记录损坏时视为没有记录，重新下载即可
+    Ok(toml::from_str(fs::read_to_string(partial_record_file).await?.as_str()).ok())
+  }
+
+  async fn write_partial_record(&self, id: &str, partial_record: &PartialRecord) -> Result<()> {
     Ok(
       fs::write(
-        self.cache_record_file.as_path(),
-        toml::to_string(cache_record)?.as_bytes(),
+        self.partial_download_dir.join(format!("{}.toml", id)),
+        toml::to_string(partial_record)?.as_bytes(),
       )
       .await?,
     )
   }
+
+  async fn remove_partial(&self, id: &str) -> Result<()> {
+    for file in [format!("{}.part", id), format!("{}.toml", id)] {
+      match fs::remove_file(self.partial_download_dir.join(file)).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+      }
+    }
+    Ok(())
+  }
+
+  /// Download `url` into the partial download dir, resuming a previous
+  /// interrupted download when the server supports range requests. Progress is reported
+  /// for `mod_url`, like the other events of the mod
+  async fn download(&self, url: &Url, mod_url: &Url, id: &str) -> Result<PathBuf> {
+    let partial_file = self.partial_download_dir.join(format!("{}.part", id));
+
+    let mut resume = None;
+    if let Some(partial_record) = self.read_partial_record(id).await?
+      && &partial_record.url == url
+      && let Some(if_range) = partial_record.if_range()
+      && let Ok(metadata) = fs::metadata(partial_file.as_path()).await
+      && metadata.len() > 0
+    {
+      resume = Some((metadata.len(), if_range.to_string()));
+    }
+
+    let (res, resumed) = loop {
+      // 分段下载不经过 http 缓存，否则响应会被完整读入内存
+      let mut req = self
+        .request(Method::GET, url)
+        .with_extension(CacheMode::NoStore);
+      if let Some((offset, if_range)) = resume.as_ref() {
+        debug!("resume download {} from {}", url, offset);
+        req = req
+          .header(RANGE, format!("bytes={}-", offset))
+          .header(IF_RANGE, if_range.as_str());
+      }
+      let res = req.send().await?;
+      let Some((offset, _)) = resume.as_ref() else {
+        break (
+          res
+            .error_for_status()
+            .map_err(reqwest_middleware::Error::from)?,
+          false,
+        );
+      };
+      let range_matched = res
+        .headers()
+        .typed_get::<ContentRange>()
+        .and_then(|x| x.bytes_range())
+        .is_some_and(|(start, _)| start == *offset);
+      match res.status() {
+        StatusCode::PARTIAL_CONTENT if range_matched => break (res, true),
+        StatusCode::PARTIAL_CONTENT | StatusCode::RANGE_NOT_SATISFIABLE => {
+          debug!("unexpected range response, restart download {}", url);
+          resume = None;
+        }
+        _ => {
+          break (
+            res
+              .error_for_status()
+              .map_err(reqwest_middleware::Error::from)?,
+            false,
+          );
+        }
+      }
+    };
+
+    let mut write = if resumed {
+      File::options()
+        .append(true)
+        .open(partial_file.as_path())
+        .await?
+    } else {
+      // 服务器不支持分段或文件已变更，从头下载
+      let header_str = |name: HeaderName| {
+        res
+          .headers()
+          .get(name)
+          .and_then(|x| x.to_str().ok())
+          .map(ToString::to_string)
+      };
+      self
+        .write_partial_record(
+          id,
+          &PartialRecord {
+            url: url.to_owned(),
+            etag: header_str(ETAG),
+            last_modified: header_str(LAST_MODIFIED),
+          },
+        )
+        .await?;
+      File::create(partial_file.as_path()).await?
+    };
+
+    let mut downloaded = if resumed {
+      write.metadata().await?.len()
+    } else {
+      0
+    };
+    let total = res.content_length().map(|x| x + downloaded);
+    let mut reported = downloaded;
+    let mut stream = res.bytes_stream();
+    while let Some(chunk) = stream
+      .try_next()
+      .await
+      .map_err(reqwest_middleware::Error::from)?
+    {
+      write.write_all(chunk.as_ref()).await?;
+      downloaded += chunk.len() as u64;
+      // 每 1 MiB 报告一次进度
+      if downloaded - reported >= 1024 * 1024 {
+        reported = downloaded;
+        self.options.reporter.report(Event::DownloadProgress {
+          url: mod_url.to_owned(),
+          downloaded,
+          total,
+        });
+      }
+    }
+    write.flush().await?;
+    self.options.reporter.report(Event::DownloadProgress {
+      url: mod_url.to_owned(),
+      downloaded,
+      total,
+    });
+
+    Ok(partial_file)
+  }
 }
 
 impl WebResolver {
-  pub fn can_resolve(&self, url: Url) -> bool {
+  pub fn can_resolve(&self, url: &Url) -> bool {
     matches!(url.scheme(), "http" | "https")
   }
 
-  pub async fn resolve(&self, url: Url) -> Result<ResolveInfo> {
-    if !self.can_resolve(url.to_owned()) {
+  pub fn cache_id(&self, url: &Url) -> Result<String> {
+    if !self.can_resolve(url) {
       return Err(Error::CannotResolve);
     }
+    Ok(hex::encode(
+      Sha256::digest(url.as_str().as_bytes()).to_vec().as_slice(),
+    ))
+  }
 
-    let res = self.reqwest_client.head(url.to_owned()).send().await?;
+  fn artifact_from_record(id: String, url: &Url, cache_record: CacheRecord) -> Artifact {
+    Artifact {
+      id,
+      url: url.to_owned(),
+      source: cache_record.url,
+      version: cache_record.version,
+      digest: cache_record.digest,
+      size: cache_record.content_length,
+      content_type: cache_record.content_type,
+      last_updated: cache_record.last_updated,
+      etag: cache_record.etag,
+    }
+  }
+
+  pub async fn resolve(&self, url: &Url) -> Result<Artifact> {
+    let id = self.cache_id(url)?;
+
+    if let Some(cache_record) = self.get_cache_record(id.as_str()).await?
+      && (self.options.offline || self.is_within_ttl(&cache_record))
+    {
+      return Ok(Self::artifact_from_record(id, url, cache_record));
+    }
+    if self.options.offline {
+      return Err(Error::NotAvailableOffline {
+        url: url.to_owned(),
+      });
+    }
+
+    // 是否过期由缓存记录判断，http 缓存必须重新验证，否则 update 会拿到旧的响应
+    let res = match self
+      .request(Method::HEAD, url)
+      .with_extension(CacheMode::NoCache)
+      .send()
+      .await
+    {
+      Ok(res) if res.status().is_success() => res,
+      res => {
+        // 部分服务器拒绝 HEAD，改为只请求第一个字节
+        debug!(
+          "HEAD {} failed: {:?}, fall back to ranged GET",
+          url,
+          res.map(|x| x.status())
+        );
+        self
+          .request(Method::GET, url)
+          .header(RANGE, "bytes=0-0")
+          .with_extension(CacheMode::NoStore)
+          .send()
+          .await?
+          .error_for_status()
+          .map_err(reqwest_middleware::Error::from)?
+      }
+    };
 
     let headers = res.headers();
-    let content_length = headers
-      .typed_get::<ContentLength>()
-      .map(|x| x.0)
-      .unwrap_or_default();
+    let size = if res.status() == StatusCode::PARTIAL_CONTENT {
+      headers
+        .typed_get::<ContentRange>()
+        .and_then(|x| x.bytes_len())
+    } else {
+      headers.typed_get::<ContentLength>().map(|x| x.0)
+    };
     let last_updated = headers
       .typed_get::<LastModified>()
       .map(Into::<SystemTime>::into)
-      .map(|x| x.into())
-      .unwrap_or_default();
-    let id = hex::encode(Sha256::digest(url.as_str().as_bytes()).to_vec().as_slice());
+      .map(Into::into);
+    let header_str = |name: HeaderName| {
+      headers
+        .get(name)
+        .and_then(|x| x.to_str().ok())
+        .map(ToString::to_string)
+    };
 
-    Ok(ResolveInfo {
-      size: content_length,
+    Ok(Artifact {
       id,
-      url,
+      url: url.to_owned(),
+      source: url.to_owned(),
+      version: None,
+      digest: parse_digest(res.status(), headers),
+      size,
+      content_type: header_str(CONTENT_TYPE),
       last_updated,
+      etag: header_str(ETAG),
     })
   }
 
-  pub async fn is_up_to_date(&self, url: Url) -> Result<bool> {
-    let cache_record = self.read_cache_record().await?;
-    let Some((_, cache_record)) = cache_record.iter().find(|(_, v)| v.url == url) else {
+  pub async fn is_up_to_date(&self, url: &Url) -> Result<bool> {
+    let Some(cache_record) = self.find_cache_record_by_url(url).await? else {
       return Ok(false);
     };
-    let latest_resolve_info = self.resolve(url.to_owned()).await?;
-    Ok(cache_record.last_updated == latest_resolve_info.last_updated)
+    if self.options.offline || self.is_within_ttl(&cache_record) {
+      return Ok(true);
+    }
+    let latest = self.resolve(url).await?;
+    Ok(cache_record.matches(&latest))
   }
 
-  pub async fn cache(&self, url: Url) -> Result<PathBuf> {
-    let resolve_info = self.resolve(url.to_owned()).await?;
-    let cache_dir = self.download_cache_dir.join(resolve_info.id.as_str());
-    if self.is_up_to_date(url.to_owned()).await? {
+  pub async fn fetch(&self, artifact: &Artifact) -> Result<PathBuf> {
+    let id = artifact.id.as_str();
+    let cache_dir = self.download_cache_dir.join(id);
+    if fs::try_exists(cache_dir.as_path()).await?
+      && let Some(mut cache_record) = self.get_cache_record(id).await?
+      && (self.options.offline
+        || self.is_within_ttl(&cache_record)
+        || cache_record.matches(artifact))
+    {
       debug!("reuse current cache: {:?}", cache_dir);
       // 不需要重新缓存
-      return Ok(cache_dir.to_owned());
+      cache_record.validated_at = Utc::now();
+      cache_record.last_used = Utc::now();
+      cache_record.size = dir_size(cache_dir.to_owned()).await?;
+      self.put_cache_record(id, &cache_record).await?;
+      return Ok(cache_dir);
+    }
+    if self.options.offline {
+      return Err(Error::NotAvailableOffline {
+        url: artifact.url.to_owned(),
+      });
     }
 
-    let cache_record = resolve_info.to_owned().into();
-    let mut cache_records = self.read_cache_record().await?;
-    cache_records.insert(resolve_info.id.to_string(), cache_record);
-    let res = self.reqwest_client.get(url.to_owned()).send().await?;
-    debug!("make temp dir");
-    let temp_dir = temp_dir::TempDir::new()?;
-    let temp_file = temp_dir.path().join("cache");
-    {
-      let mut read = StreamReader::new(res.bytes_stream().map_err(std::io::Error::other));
-      let mut write = File::options()
-        .create_new(true)
-        .write(true)
-        .open(temp_file.as_path())
-        .await?;
-      io::copy(&mut read, &mut write).await?;
+    let partial_file = self.download(&artifact.source, &artifact.url, id).await?;
+    if let Some(expected) = artifact.digest.as_ref() {
+      let actual = file_sha256(partial_file.as_path()).await?;
+      if &actual != expected {
+        self.remove_partial(id).await?;
+        return Err(Error::DigestMismatch {
+          url: Box::new(artifact.url.to_owned()),
+          expected: expected.to_owned(),
+          actual,
+        });
+      }
     }
     debug!("empty cache dir: {:?}", cache_dir);
     empty_dir(cache_dir.as_path()).await?;
-    debug!("unzip {:?} -> {:?}", temp_file, cache_dir);
-    unzip_file(File::open(temp_file.as_path()).await?, cache_dir.as_path()).await?;
-    self.write_cache_record(&cache_records).await?;
+    debug!("unzip {:?} -> {:?}", partial_file, cache_dir);
+    unzip_file(
+      File::open(partial_file.as_path()).await?,
+      cache_dir.as_path(),
+    )
+    .await?;
+    self.remove_partial(id).await?;
+    let mut cache_record = CacheRecord::from(artifact);
+    cache_record.size = dir_size(cache_dir.to_owned()).await?;
+    self.put_cache_record(id, &cache_record).await?;
     Ok(cache_dir)
   }
 
   pub async fn clear_cache(&self) -> Result<()> {
     empty_dir(self.download_cache_dir.as_path()).await?;
+    empty_dir(self.partial_download_dir.as_path()).await?;
+    self.remove_cache_records(None).await?;
+    Ok(())
+  }
+
+  pub async fn cache_entries(&self) -> Result<Vec<CacheEntry>> {
+    let mut entries = self
+      .list_cache_records()
+      .await?
+      .into_iter()
+      .map(|(id, cache_record)| CacheEntry {
+        path: self.download_cache_dir.join(id.as_str()),
+        id,
+        url: cache_record.url,
+        size: cache_record.size,
+        last_updated: cache_record.last_updated,
+        last_used: cache_record.last_used,
+      })
+      .collect::<Vec<_>>();
+    entries.sort_by_key(|x| std::cmp::Reverse(x.last_used));
+    Ok(entries)
+  }
+
+  pub async fn remove_cache(&self, id: &str) -> Result<()> {
+    let cache_dir = self.download_cache_dir.join(id);
+    if fs::try_exists(cache_dir.as_path()).await? {
+      fs::remove_dir_all(cache_dir).await?;
+    }
+    self.remove_partial(id).await?;
+    self.remove_cache_records(Some(id)).await?;
     Ok(())
   }
 }
 
 #[async_trait]
 impl Resolver for WebResolver {
-  fn can_resolve(&self, url: Url) -> bool {
+  fn name(&self) -> &str {
+    "web"
+  }
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      versions: false,
+      offline: true,
+    }
+  }
+  fn priority(&self) -> i32 {
+    WEB_RESOLVER_PRIORITY
+  }
+  fn can_resolve(&self, url: &Url) -> bool {
     self.can_resolve(url)
   }
-  async fn resolve(&self, url: Url) -> Result<ResolveInfo> {
+  fn cache_id(&self, url: &Url) -> Result<String> {
+    self.cache_id(url)
+  }
+  async fn resolve(&self, url: &Url) -> Result<Artifact> {
     self.resolve(url).await
   }
-  async fn is_up_to_date(&self, url: Url) -> Result<bool> {
+  async fn is_up_to_date(&self, url: &Url) -> Result<bool> {
     self.is_up_to_date(url).await
   }
-  async fn cache(&self, url: Url) -> Result<PathBuf> {
-    self.cache(url).await
+  async fn fetch(&self, artifact: &Artifact) -> Result<PathBuf> {
+    self.fetch(artifact).await
   }
   async fn clear_cache(&self) -> Result<()> {
     self.clear_cache().await
   }
+  async fn cache_entries(&self) -> Result<Vec<CacheEntry>> {
+    self.cache_entries().await
+  }
+  async fn remove_cache(&self, id: &str) -> Result<()> {
+    self.remove_cache(id).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // sha-256 of "hello"
+  const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+  const HELLO_BASE64: &str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+
+  fn headers(pairs: &[(&'static str, String)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+      headers.insert(*name, value.parse().unwrap());
+    }
+    headers
+  }
+
+  #[test]
+  fn parse_digest_headers() {
+    for name in ["repr-digest", "content-digest", "digest"] {
+      let headers = headers(&[(name, format!("sha-256=:{HELLO_BASE64}:"))]);
+      assert_eq!(
+        parse_digest(StatusCode::OK, &headers).as_deref(),
+        Some(HELLO)
+      );
+    }
+  }
+
+  #[test]
+  fn parse_digest_picks_sha256() {
+    let headers = headers(&[(
+      "repr-digest",
+      format!("sha-512=:AAAA:, SHA-256=:{HELLO_BASE64}:"),
+    )]);
+    assert_eq!(
+      parse_digest(StatusCode::OK, &headers).as_deref(),
+      Some(HELLO)
+    );
+    let headers = self::headers(&[("repr-digest", "sha-512=:AAAA:".to_string())]);
+    assert_eq!(parse_digest(StatusCode::OK, &headers), None);
+  }
+
+  #[test]
+  fn parse_digest_partial_content() {
+    // 范围请求时 Content-Digest 只是那一个字节的摘要
+    let one_byte = "qqlAJmTxpB9A67xSyZk+tmrrNmYClY/fqig7ceZNsSM=";
+    let headers = self::headers(&[("content-digest", format!("sha-256=:{one_byte}:"))]);
+    assert_eq!(parse_digest(StatusCode::PARTIAL_CONTENT, &headers), None);
+
+    let headers = self::headers(&[
+      ("content-digest", format!("sha-256=:{one_byte}:")),
+      ("repr-digest", format!("sha-256=:{HELLO_BASE64}:")),
+    ]);
+    assert_eq!(
+      parse_digest(StatusCode::PARTIAL_CONTENT, &headers).as_deref(),
+      Some(HELLO)
+    );
+    let headers = self::headers(&[("digest", format!("sha-256={HELLO_BASE64}"))]);
+    assert_eq!(
+      parse_digest(StatusCode::PARTIAL_CONTENT, &headers).as_deref(),
+      Some(HELLO)
+    );
+  }
 }
@@ -1,6 +1,24 @@
 use url::Url;
 
-use crate::cli::{Cli, Command};
+use crate::{
+  cli::{CacheCommand, Cli, Command, DryRunArgs},
+  kmf::GameRef,
+};
+
+/// Only report what would change in the game
+#[derive(Debug, Clone, Copy)]
+pub struct DryRun {
+  /// Download mods not in cache
+  pub download: bool,
+}
+
+impl DryRun {
+  fn from_cli(args: &DryRunArgs) -> Option<Self> {
+    args.dry_run.then_some(Self {
+      download: args.download,
+    })
+  }
+}
 
 pub enum Task {
   /// Install mods
@@ -8,19 +26,121 @@ pub enum Task {
     /// Mods url
     /// Note: only supports `https`/`http` or `kmf`
     url: Vec<Url>,
-    /// Game url
-    /// Note: only supports `file` scheme for now
-    game: Option<Url>,
+    /// Game url or name, the default game when `None`
+    game: Option<GameRef>,
+    dry_run: Option<DryRun>,
   },
+  /// Update installed mods
+  Update {
+    /// Mods url, all mods installed in the game when empty
+    url: Vec<Url>,
+    /// Game url or name
+    game: Option<GameRef>,
+    dry_run: Option<DryRun>,
+  },
+  /// Uninstall mods
+  Uninstall {
+    /// Mods url
+    url: Vec<Url>,
+    /// Game url or name, mods are removed from every version unless `version` is given
+    game: Option<GameRef>,
+    dry_run: Option<DryRun>,
+  },
+  /// Check installed mods against their sources
+  Verify {
+    /// Mods url, all mods installed in the game when empty
+    url: Vec<Url>,
+    /// Game url or name, every version is checked unless `version` is given
+    game: Option<GameRef>,
+    /// Restore modified and missing files
+    repair: bool,
+  },
+  /// Diagnose the environment
+  Doctor {
+    /// Game url or name, game checks are skipped without one
+    game: Option<GameRef>,
+  },
+  /// List cached mods
+  CacheList,
+  /// Show details of a cached mod
+  CacheInfo {
+    /// Cache id (or its prefix) or mod url
+    entry: String,
+  },
+  /// Clear cache
+  CacheClear {
+    /// Resolver name, all resolvers when `None`
+    resolver: Option<String>,
+    /// Cache ids (or their prefix) or mod urls, everything when empty
+    entries: Vec<String>,
+  },
+  /// Remove cached mods not referenced by any installed mod
+  CacheGc,
 }
 
 impl Task {
+  /// Name of the task, as reported in events
+  pub fn name(&self) -> &'static str {
+    match self {
+      Task::Install { .. } => "install",
+      Task::Update { .. } => "update",
+      Task::Uninstall { .. } => "uninstall",
+      Task::Verify { .. } => "verify",
+      Task::Doctor { .. } => "doctor",
+      Task::CacheList => "cache_list",
+      Task::CacheInfo { .. } => "cache_info",
+      Task::CacheClear { .. } => "cache_clear",
+      Task::CacheGc => "cache_gc",
+    }
+  }
+
+  /// The task may download mods into the cache, so the cache is evicted after it
+  pub fn fills_cache(&self) -> bool {
+    match self {
+      Task::Install { dry_run, .. } | Task::Update { dry_run, .. } => dry_run.is_none(),
+      Task::Verify { repair, .. } => *repair,
+      _ => false,
+    }
+  }
+
   /// Construct task from cli
   pub fn from_cli(cli: &Cli) -> Vec<Task> {
     match &cli.command {
-      Command::Install { url, game } => vec![Task::Install {
+      Command::Install { url, game, dry_run } => vec![Task::Install {
+        url: url.to_owned(),
+        game: game.to_owned(),
+        dry_run: DryRun::from_cli(dry_run),
+      }],
+      Command::Update { url, game, dry_run } => vec![Task::Update {
+        url: url.to_owned(),
+        game: game.to_owned(),
+        dry_run: DryRun::from_cli(dry_run),
+      }],
+      Command::Uninstall { url, game, dry_run } => vec![Task::Uninstall {
+        url: url.to_owned(),
+        game: game.to_owned(),
+        dry_run: DryRun::from_cli(dry_run),
+      }],
+      Command::Verify { url, game, repair } => vec![Task::Verify {
         url: url.to_owned(),
         game: game.to_owned(),
+        repair: *repair,
+      }],
+      Command::Doctor { game } => vec![Task::Doctor {
+        game: game.to_owned(),
+      }],
+      // 配置和游戏命令不需要 Kmf，在加载配置前处理
+      Command::Config { .. } | Command::Game { .. } => vec![],
+      Command::Cache { command } => vec![match command {
+        CacheCommand::List => Task::CacheList,
+        CacheCommand::Info { entry } => Task::CacheInfo {
+          entry: entry.to_owned(),
+        },
+        CacheCommand::Clear { resolver, entry } => Task::CacheClear {
+          resolver: resolver.to_owned(),
+          entries: entry.to_owned(),
+        },
+        CacheCommand::Gc => Task::CacheGc,
       }],
     }
   }
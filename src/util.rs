@@ -3,16 +3,19 @@ use std::path::{Path, PathBuf};
 use async_zip::base::read::seek::ZipFileReader;
 use error::UnzipFileError;
 use futures::{FutureExt, future::BoxFuture};
-use indicatif::{MultiProgress, ProgressBar};
+use sha2::{Digest, Sha256};
 use tokio::{
   fs::{self, File, OpenOptions, create_dir_all},
-  io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+  io::{AsyncReadExt, BufReader},
 };
 
 use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 
 pub mod error;
+pub mod lock;
+pub mod netrc;
 pub mod reqwest;
+pub mod store;
 
 pub use error::GetGameVersionsError;
 use tracing::warn;
@@ -34,12 +37,12 @@ pub async fn unzip_file(archive: File, out_dir: &Path) -> Result<(), UnzipFileEr
   let mut reader = ZipFileReader::new(archive).await?;
   for index in 0..reader.file().entries().len() {
     let entry = reader.file().entries().get(index).unwrap();
-    let path = out_dir.join(sanitize_file_path(entry.filename().as_str().unwrap()));
+    let path = out_dir.join(sanitize_file_path(entry.filename().as_str()?));
     // If the filename of the entry ends with '/', it is treated as a directory.
     // This is implemented by previous versions of this crate and the Python Standard Library.
     // https://docs.rs/async_zip/0.0.8/src/async_zip/read/mod.rs.html#63-65
     // https://github.com/python/cpython/blob/820ef62833bd2d84a141adedd9a05998595d6b6d/Lib/zipfile.py#L528
-    let entry_is_dir = entry.dir().unwrap();
+    let entry_is_dir = entry.dir()?;
 
     let mut entry_reader = reader.reader_without_entry(index).await?;
 
@@ -102,7 +105,7 @@ pub async fn get_game_versions(
       versions.push((file_name, file_name_num));
     }
 
-    versions.sort_by(|x, y| x.1.cmp(&y.1));
+    versions.sort_by_key(|x| x.1);
     versions
       .into_iter()
       .map(|(x, _)| x.to_string())
@@ -113,28 +116,17 @@ pub async fn get_game_versions(
   Ok(versions)
 }
 
-#[allow(dead_code)]
-pub async fn io_copy_with_progressbar(
-  mut read: impl AsyncRead + Unpin,
-  mut write: impl AsyncWrite + Unpin,
-  len: u64,
-  title: impl Into<String>,
-  multi_progress: Option<&MultiProgress>,
-) -> Result<(), GetGameVersionsError> {
-  let pb = multi_progress
-    .map(|x| x.add(ProgressBar::new(len)))
-    .unwrap_or_else(|| ProgressBar::new(len));
-  pb.set_message(title.into());
-  let mut buf = [0u8; 1024];
+/// Hex encoded sha-256 of the file content
+pub async fn file_sha256(file_path: &Path) -> Result<String, std::io::Error> {
+  let mut file = File::open(file_path).await?;
+  let mut hasher = Sha256::new();
+  let mut buf = vec![0u8; 64 * 1024];
   loop {
-    let len = read.read(buf.as_mut()).await?;
+    let len = file.read(buf.as_mut()).await?;
     if len == 0 {
-      pb.finish();
-      return Ok(());
+      return Ok(hex::encode(hasher.finalize()));
     }
-
-    write.write_all(buf[..len].as_ref()).await?;
-    pb.inc(len as u64);
+    hasher.update(&buf[..len]);
   }
 }
 
@@ -162,33 +154,38 @@ pub async fn ensure_dir(dir_path: &Path) -> Result<&Path, std::io::Error> {
   Ok(dir_path)
 }
 
-pub async fn ensure_file(file_path: &Path) -> Result<&Path, std::io::Error> {
-  if !fs::try_exists(file_path).await? {
-    fs::create_dir_all(file_path.parent().expect("File always has parent")).await?;
-    File::create_new(file_path).await?;
-  } else if fs::metadata(file_path).await?.is_dir() {
-    fs::remove_dir_all(file_path).await?;
-    File::create_new(file_path).await?;
+fn dir_size_inner(dir: PathBuf) -> BoxFuture<'static, Result<u64, tokio::io::Error>> {
+  async move {
+    let mut size = 0;
+    let mut entries = fs::read_dir(dir).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+      let metadata = entry.metadata().await?;
+      if metadata.is_dir() {
+        size += dir_size(entry.path()).await?;
+      } else {
+        size += metadata.len();
+      }
+    }
+    Ok(size)
   }
-  Ok(file_path)
+  .boxed()
 }
 
-fn async_copy_dir_inner(
-  src: PathBuf,
-  dst: PathBuf,
-) -> BoxFuture<'static, Result<(), tokio::io::Error>> {
+fn list_files_inner(
+  dir: PathBuf,
+  prefix: PathBuf,
+  files: &mut Vec<PathBuf>,
+) -> BoxFuture<'_, Result<(), tokio::io::Error>> {
   async move {
-    fs::create_dir_all(dst.as_path()).await?;
-    let mut entries = fs::read_dir(src).await?;
+    let mut entries = fs::read_dir(dir).await?;
 
     while let Some(entry) = entries.next_entry().await? {
-      let path = entry.path();
-      let target = dst.join(entry.file_name());
-
-      if path.is_dir() {
-        async_copy_dir(path, target).await?;
+      let path = prefix.join(entry.file_name());
+      if entry.metadata().await?.is_dir() {
+        list_files_inner(entry.path(), path, files).await?;
       } else {
-        fs::copy(&path, &target).await?;
+        files.push(path);
       }
     }
     Ok(())
@@ -196,6 +193,31 @@ fn async_copy_dir_inner(
   .boxed()
 }
 
-pub async fn async_copy_dir(src: PathBuf, dst: PathBuf) -> Result<(), tokio::io::Error> {
-  async_copy_dir_inner(src, dst).await
+/// All files under `dir`, relative to it
+pub async fn list_files(dir: PathBuf) -> Result<Vec<PathBuf>, tokio::io::Error> {
+  let mut files = Vec::new();
+  list_files_inner(dir, PathBuf::new(), &mut files).await?;
+  files.sort();
+  Ok(files)
+}
+
+/// Total size of all files under `dir`
+pub async fn dir_size(dir: PathBuf) -> Result<u64, tokio::io::Error> {
+  dir_size_inner(dir).await
+}
+
+/// Format bytes as human readable size, e.g. `1.5 MiB`
+pub fn format_size(size: u64) -> String {
+  const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let mut size = size as f64;
+  let mut unit = 0;
+  while size >= 1024.0 && unit < UNITS.len() - 1 {
+    size /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{} {}", size, UNITS[unit])
+  } else {
+    format!("{:.1} {}", size, UNITS[unit])
+  }
 }
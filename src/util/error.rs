@@ -1,3 +1,7 @@
+use std::path::PathBuf;
+
+use crate::i18n::Msg;
+
 #[derive(Debug, thiserror::Error)]
 pub enum GetGameVersionsError {
   #[error("Game dir is not a dir")]
@@ -8,6 +12,35 @@ pub enum GetGameVersionsError {
   Io(#[from] std::io::Error),
 }
 
+impl GetGameVersionsError {
+  /// Localized message, `None` for errors only meaningful in English
+  pub fn message(&self) -> Option<Msg<'_>> {
+    match self {
+      GetGameVersionsError::GameDirIsNotADir => Some(Msg::GameDirIsNotADir),
+      GetGameVersionsError::IllegalGameDirStructure => Some(Msg::IllegalGameDirStructure),
+      GetGameVersionsError::Io(_) => None,
+    }
+  }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LockError {
+  #[error("another kmf is running (lock held on {path:?}), use `--wait` to wait for it")]
+  Locked { path: PathBuf },
+  #[error("std::io: {0}")]
+  Io(#[from] std::io::Error),
+}
+
+impl LockError {
+  /// Localized message, `None` for errors only meaningful in English
+  pub fn message(&self) -> Option<Msg<'_>> {
+    match self {
+      LockError::Locked { path } => Some(Msg::Locked { path }),
+      LockError::Io(_) => None,
+    }
+  }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum UnzipFileError {
   #[error("async_zip: {0}")]
@@ -15,3 +48,37 @@ pub enum UnzipFileError {
   #[error("std::io: {0}")]
   Io(#[from] std::io::Error),
 }
+
+#[derive(Debug, thiserror::Error)]
+pub enum BuildClientError {
+  #[error("cannot read root certificate {path:?}")]
+  ReadCertificate {
+    path: PathBuf,
+    source: std::io::Error,
+  },
+  #[error("cannot read netrc {path:?}")]
+  ReadNetrc {
+    path: PathBuf,
+    source: std::io::Error,
+  },
+  #[error("reqwest: {0}")]
+  Reqwest(#[from] reqwest::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+  #[error("redb: {0}")]
+  Database(#[from] redb::DatabaseError),
+  #[error("redb: {0}")]
+  Transaction(#[from] redb::TransactionError),
+  #[error("redb: {0}")]
+  Table(#[from] redb::TableError),
+  #[error("redb: {0}")]
+  Storage(#[from] redb::StorageError),
+  #[error("redb: {0}")]
+  Commit(#[from] redb::CommitError),
+  #[error("serde_json: {0}")]
+  Json(#[from] serde_json::Error),
+  #[error("{0}")]
+  Lock(#[from] LockError),
+}
@@ -0,0 +1,38 @@
+use std::{
+  fs::{File, OpenOptions, TryLockError},
+  path::PathBuf,
+};
+
+use tracing::info;
+
+use super::error::LockError;
+
+/// Advisory lock on a lock file, released when dropped
+#[derive(Debug)]
+pub struct FileLock {
+  _file: File,
+}
+
+impl FileLock {
+  /// Lock `path` exclusively. When another process holds it, wait for it if
+  /// `wait`, otherwise fail with [`LockError::Locked`].
+  pub async fn acquire(path: PathBuf, wait: bool) -> Result<Self, LockError> {
+    let file = OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(path.as_path())?;
+    match file.try_lock() {
+      Ok(()) => Ok(Self { _file: file }),
+      Err(TryLockError::WouldBlock) if wait => {
+        info!("waiting for another kmf to release {:?}", path);
+        let file = tokio::task::spawn_blocking(move || file.lock().map(|_| file))
+          .await
+          .expect("lock task panicked")?;
+        Ok(Self { _file: file })
+      }
+      Err(TryLockError::WouldBlock) => Err(LockError::Locked { path }),
+      Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+  }
+}
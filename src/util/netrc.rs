@@ -0,0 +1,177 @@
+use std::fmt;
+
+use crate::config::Secret;
+
+/// Credentials of a machine in netrc
+#[derive(Debug, Clone, Default)]
+pub struct Machine {
+  pub login: Option<String>,
+  pub password: Option<Secret>,
+}
+
+/// Parsed netrc file, only `machine`, `default`, `login` and `password` are used
+#[derive(Default)]
+pub struct Netrc {
+  machines: Vec<(String, Machine)>,
+  default: Option<Machine>,
+}
+
+impl Netrc {
+  pub fn parse(content: &str) -> Self {
+    let mut netrc = Self::default();
+    // 当前条目，None 表示 default
+    let mut current: Option<(Option<String>, Machine)> = None;
+    // 等待取值的关键字，值可以在下一行
+    let mut keyword = None;
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+      for token in line.split_whitespace() {
+        match keyword.take() {
+          Some("machine") => current = Some((Some(token.to_string()), Machine::default())),
+          Some("login") => {
+            if let Some((_, machine)) = current.as_mut() {
+              machine.login = Some(token.to_string());
+            }
+          }
+          Some("password") => {
+            if let Some((_, machine)) = current.as_mut() {
+              machine.password = Some(Secret::new(token.to_string()));
+            }
+          }
+          Some(_) => {}
+          None => match token {
+            "machine" => {
+              netrc.push(current.take());
+              keyword = Some(token);
+            }
+            "default" => {
+              netrc.push(current.take());
+              current = Some((None, Machine::default()));
+            }
+            "login" | "password" | "account" => keyword = Some(token),
+            "macdef" => {
+              // 宏定义到空行为止
+              for line in lines.by_ref() {
+                if line.trim().is_empty() {
+                  break;
+                }
+              }
+              break;
+            }
+            _ => {}
+          },
+        }
+      }
+    }
+    netrc.push(current);
+    netrc
+  }
+
+  fn push(&mut self, entry: Option<(Option<String>, Machine)>) {
+    match entry {
+      Some((Some(name), machine)) => self.machines.push((name, machine)),
+      Some((None, machine)) => self.default = Some(machine),
+      None => {}
+    }
+  }
+
+  /// Credentials of the `machine` entry for `host`
+  pub fn find(&self, host: &str) -> Option<&Machine> {
+    self
+      .machines
+      .iter()
+      .find(|(name, _)| name.eq_ignore_ascii_case(host))
+      .map(|(_, machine)| machine)
+  }
+
+  /// Credentials of the `default` entry, used for hosts without their own
+  pub fn default_machine(&self) -> Option<&Machine> {
+    self.default.as_ref()
+  }
+}
+
+impl fmt::Debug for Netrc {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Netrc")
+      .field(
+        "machines",
+        &self
+          .machines
+          .iter()
+          .map(|(name, _)| name)
+          .collect::<Vec<_>>(),
+      )
+      .field("default", &self.default.is_some())
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn credentials(machine: Option<&Machine>) -> Option<(Option<&str>, Option<&str>)> {
+    machine.map(|x| (x.login.as_deref(), x.password.as_ref().map(Secret::expose)))
+  }
+
+  #[test]
+  fn parse_machines() {
+    let netrc = Netrc::parse(
+      "machine example.com login alice password secret\n\
+       machine files.example.com\n  login bob\n  account ignored\n  password hunter2\n",
+    );
+    assert_eq!(
+      credentials(netrc.find("example.com")),
+      Some((Some("alice"), Some("secret")))
+    );
+    assert_eq!(
+      credentials(netrc.find("FILES.example.com")),
+      Some((Some("bob"), Some("hunter2")))
+    );
+    assert!(netrc.find("other.com").is_none());
+    assert!(netrc.default_machine().is_none());
+  }
+
+  #[test]
+  fn parse_default() {
+    let netrc =
+      Netrc::parse("machine example.com login alice\ndefault login anonymous password me");
+    assert_eq!(
+      credentials(netrc.find("example.com")),
+      Some((Some("alice"), None))
+    );
+    assert!(netrc.find("other.com").is_none());
+    assert_eq!(
+      credentials(netrc.default_machine()),
+      Some((Some("anonymous"), Some("me")))
+    );
+  }
+
+  #[test]
+  fn parse_skips_macdef() {
+    let netrc = Netrc::parse(
+      "macdef init\nmachine evil.com login mallory\n\nmachine example.com login alice",
+    );
+    assert!(netrc.find("evil.com").is_none());
+    assert_eq!(
+      credentials(netrc.find("example.com")),
+      Some((Some("alice"), None))
+    );
+  }
+
+  #[test]
+  fn parse_values_on_next_line() {
+    let netrc = Netrc::parse("machine\nexample.com login\nalice");
+    assert_eq!(
+      credentials(netrc.find("example.com")),
+      Some((Some("alice"), None))
+    );
+  }
+
+  #[test]
+  fn parse_ignores_tokens_outside_entries() {
+    let netrc = Netrc::parse("login nobody password nothing\nmachine");
+    assert!(netrc.find("nobody").is_none());
+    assert!(netrc.default_machine().is_none());
+  }
+}
@@ -1 +1,131 @@
+use std::{path::PathBuf, time::Duration};
+
+use http_cache_reqwest::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
+use reqwest::{Certificate, NoProxy, Proxy};
+use reqwest_middleware::ClientWithMiddleware;
+use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
+use reqwest_tracing::TracingMiddleware;
+
+use crate::config::{HostConfig, NetworkConfig};
+
+use super::{error::BuildClientError, netrc::Netrc};
+
 pub mod middleware;
+
+/// Proxies of `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY` (or their lower case forms) like
+/// reqwest reads them when no proxy is configured, scheme specific ones first. Invalid ones
+/// are skipped like reqwest does
+fn env_proxies() -> Vec<Proxy> {
+  let var = |name: &str| {
+    [name.to_string(), name.to_ascii_lowercase()]
+      .into_iter()
+      .find_map(|x| std::env::var(x).ok())
+      .filter(|x| !x.trim().is_empty())
+  };
+  // CGI 中 HTTP_PROXY 可能来自请求头
+  let cgi = std::env::var_os("REQUEST_METHOD").is_some();
+  [
+    var("HTTPS_PROXY").and_then(|x| Proxy::https(x).ok()),
+    var("HTTP_PROXY")
+      .filter(|_| !cgi)
+      .and_then(|x| Proxy::http(x).ok()),
+    var("ALL_PROXY").and_then(|x| Proxy::all(x).ok()),
+  ]
+  .into_iter()
+  .flatten()
+  .map(|x| x.no_proxy(NoProxy::from_env()))
+  .collect()
+}
+
+/// Build the http client shared by all resolvers, caching responses in `http_cache_dir`.
+/// When `offline`, responses only come from the http cache.
+pub fn build_client(
+  http_cache_dir: PathBuf,
+  offline: bool,
+  network: &NetworkConfig,
+) -> Result<ClientWithMiddleware, BuildClientError> {
+  let mut builder = reqwest::Client::builder();
+
+  // 先按主机匹配代理，最具体的主机优先，没有匹配时再用全局代理
+  let mut host_proxies = network
+    .hosts
+    .iter()
+    .filter_map(|(host, host_config)| {
+      Some((
+        host.to_owned(),
+        reqwest::Url::parse(host_config.proxy.as_ref()?).ok()?,
+      ))
+    })
+    .collect::<Vec<_>>();
+  host_proxies.sort_by_key(|(host, _)| std::cmp::Reverse(host.len()));
+  let has_host_proxies = !host_proxies.is_empty();
+  if has_host_proxies {
+    builder = builder.proxy(Proxy::custom(move |url| {
+      let host = url.host_str()?;
+      host_proxies
+        .iter()
+        .find(|(pattern, _)| HostConfig::matches(pattern, host))
+        .map(|(_, proxy)| proxy.to_owned())
+    }));
+  }
+  if let Some(proxy) = network.proxy.as_ref() {
+    builder = builder.proxy(
+      Proxy::all(proxy)?.no_proxy(network.no_proxy.as_deref().and_then(NoProxy::from_string)),
+    );
+  } else if has_host_proxies {
+    // 设置任何代理后 reqwest 不再读取环境变量中的代理，其余主机需要显式回落
+    for proxy in env_proxies() {
+      builder = builder.proxy(proxy);
+    }
+  }
+
+  if let Some(connect_timeout) = network.connect_timeout {
+    builder = builder.connect_timeout(Duration::from_secs(connect_timeout));
+  }
+  if let Some(read_timeout) = network.read_timeout {
+    builder = builder.read_timeout(Duration::from_secs(read_timeout));
+  }
+  for path in network.root_certificates.iter() {
+    let pem = std::fs::read(path).map_err(|source| BuildClientError::ReadCertificate {
+      path: path.to_owned(),
+      source,
+    })?;
+    for certificate in Certificate::from_pem_bundle(pem.as_slice())? {
+      builder = builder.add_root_certificate(certificate);
+    }
+  }
+  if let Some(user_agent) = network.user_agent.as_ref() {
+    builder = builder.user_agent(user_agent);
+  }
+
+  let netrc = network
+    .netrc_file()
+    .map(|path| {
+      std::fs::read_to_string(path.as_path())
+        .map(|x| Netrc::parse(x.as_str()))
+        .map_err(|source| BuildClientError::ReadNetrc { path, source })
+    })
+    .transpose()?;
+
+  Ok(
+    reqwest_middleware::ClientBuilder::new(builder.build()?)
+      .with(middleware::HostOverrides::new(network.hosts.to_owned()))
+      .with(middleware::Auth::new(network.hosts.to_owned(), netrc))
+      .with(TracingMiddleware::default())
+      .with(RetryTransientMiddleware::new_with_policy(
+        ExponentialBackoff::builder().build_with_max_retries(network.retries),
+      ))
+      .with(Cache(HttpCache {
+        mode: if offline {
+          CacheMode::OnlyIfCached
+        } else {
+          CacheMode::Default
+        },
+        manager: CACacheManager {
+          path: http_cache_dir,
+        },
+        options: HttpCacheOptions::default(),
+      }))
+      .build(),
+  )
+}
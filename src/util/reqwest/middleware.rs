@@ -1 +1,260 @@
+use std::time::Duration;
 
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{
+  Extensions, HeaderValue,
+  header::{AUTHORIZATION, USER_AGENT},
+};
+use reqwest::{Request, Response, Url};
+use reqwest_middleware::{Middleware, Next, Result};
+
+use crate::{config::HostConfig, util::netrc::Netrc};
+
+/// Applies `[network.hosts]` timeouts and user agents to matching requests
+pub struct HostOverrides {
+  hosts: Vec<(String, HostConfig)>,
+}
+
+impl HostOverrides {
+  pub fn new(hosts: impl IntoIterator<Item = (String, HostConfig)>) -> Self {
+    let mut hosts = hosts.into_iter().collect::<Vec<_>>();
+    // 更具体的主机优先
+    hosts.sort_by_key(|(host, _)| std::cmp::Reverse(host.len()));
+    Self { hosts }
+  }
+
+  fn find(&self, host: &str) -> Option<&HostConfig> {
+    self
+      .hosts
+      .iter()
+      .find(|(pattern, _)| HostConfig::matches(pattern, host))
+      .map(|(_, host_config)| host_config)
+  }
+}
+
+#[async_trait::async_trait]
+impl Middleware for HostOverrides {
+  async fn handle(
+    &self,
+    mut req: Request,
+    extensions: &mut Extensions,
+    next: Next<'_>,
+  ) -> Result<Response> {
+    if let Some(host_config) = req.url().host_str().and_then(|host| self.find(host)) {
+      if let Some(timeout) = host_config.timeout {
+        *req.timeout_mut() = Some(Duration::from_secs(timeout));
+      }
+      if let Some(user_agent) = host_config.user_agent.as_ref()
+        && let Ok(user_agent) = HeaderValue::from_str(user_agent)
+      {
+        req.headers_mut().insert(USER_AGENT, user_agent);
+      }
+    }
+    next.run(req, extensions).await
+  }
+}
+
+/// Extension of requests to hosts the user did not name, like the kmf station.
+/// They never get the credentials of the netrc `default` entry
+#[derive(Debug, Clone, Copy)]
+pub struct IndirectHost;
+
+/// Adds credentials from `[network.hosts]` or netrc to https requests without `Authorization`
+#[derive(Debug)]
+pub struct Auth {
+  hosts: Vec<(String, HostConfig)>,
+  /// Hosts allowed to get credentials over plain http
+  http_hosts: Vec<String>,
+  netrc: Option<Netrc>,
+}
+
+impl Auth {
+  pub fn new(hosts: impl IntoIterator<Item = (String, HostConfig)>, netrc: Option<Netrc>) -> Self {
+    let mut hosts = hosts.into_iter().collect::<Vec<_>>();
+    let http_hosts = hosts
+      .iter()
+      .filter(|(_, host_config)| host_config.allow_http_auth)
+      .map(|(host, _)| host.to_owned())
+      .collect();
+    hosts.retain(|(_, host_config)| host_config.token.is_some() || host_config.username.is_some());
+    hosts.sort_by_key(|(host, _)| std::cmp::Reverse(host.len()));
+    Self {
+      hosts,
+      http_hosts,
+      netrc,
+    }
+  }
+
+  fn authorization(&self, url: &Url, indirect: bool) -> Option<HeaderValue> {
+    let host = url.host_str()?;
+    if url.scheme() != "https"
+      && !self
+        .http_hosts
+        .iter()
+        .any(|pattern| HostConfig::matches(pattern, host))
+    {
+      return None;
+    }
+    let (username, password) = match self
+      .hosts
+      .iter()
+      .find(|(pattern, _)| HostConfig::matches(pattern, host))
+    {
+      Some((_, host_config)) => {
+        if let Some(token) = host_config.token.as_ref() {
+          return bearer(token.expose());
+        }
+        (
+          host_config.username.to_owned()?,
+          host_config
+            .password
+            .as_ref()
+            .map(|x| x.expose().to_string()),
+        )
+      }
+      None => {
+        let netrc = self.netrc.as_ref()?;
+        let machine = netrc
+          .find(host)
+          .or_else(|| netrc.default_machine().filter(|_| !indirect))?;
+        (
+          machine.login.to_owned()?,
+          machine.password.as_ref().map(|x| x.expose().to_string()),
+        )
+      }
+    };
+    let credentials =
+      BASE64_STANDARD.encode(format!("{}:{}", username, password.unwrap_or_default()));
+    sensitive(format!("Basic {credentials}"))
+  }
+}
+
+fn bearer(token: &str) -> Option<HeaderValue> {
+  sensitive(format!("Bearer {token}"))
+}
+
+/// Header value hidden from `Debug` of requests
+fn sensitive(value: String) -> Option<HeaderValue> {
+  let mut value = HeaderValue::from_str(value.as_str()).ok()?;
+  value.set_sensitive(true);
+  Some(value)
+}
+
+#[async_trait::async_trait]
+impl Middleware for Auth {
+  async fn handle(
+    &self,
+    mut req: Request,
+    extensions: &mut Extensions,
+    next: Next<'_>,
+  ) -> Result<Response> {
+    let indirect = extensions.get::<IndirectHost>().is_some();
+    if !req.headers().contains_key(AUTHORIZATION)
+      && let Some(authorization) = self.authorization(req.url(), indirect)
+    {
+      req.headers_mut().insert(AUTHORIZATION, authorization);
+    }
+    next.run(req, extensions).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::Secret;
+
+  fn auth(hosts: Vec<(&str, HostConfig)>, netrc: &str) -> Auth {
+    Auth::new(
+      hosts
+        .into_iter()
+        .map(|(host, host_config)| (host.to_string(), host_config)),
+      Some(Netrc::parse(netrc)),
+    )
+  }
+
+  fn url(url: &str) -> Url {
+    Url::parse(url).unwrap()
+  }
+
+  fn basic(credentials: &str) -> Option<HeaderValue> {
+    sensitive(format!("Basic {}", BASE64_STANDARD.encode(credentials)))
+  }
+
+  #[test]
+  fn host_credentials() {
+    let token = HostConfig {
+      token: Some(Secret::new("t0ken".to_string())),
+      ..Default::default()
+    };
+    let basic_auth = HostConfig {
+      username: Some("alice".to_string()),
+      password: Some(Secret::new("secret".to_string())),
+      ..Default::default()
+    };
+    let auth = auth(
+      vec![("example.com", token), ("files.example.com", basic_auth)],
+      "",
+    );
+    assert_eq!(
+      auth.authorization(&url("https://api.example.com/mod"), false),
+      bearer("t0ken")
+    );
+    assert_eq!(
+      auth.authorization(&url("https://files.example.com/mod"), false),
+      basic("alice:secret")
+    );
+    assert_eq!(
+      auth.authorization(&url("https://example.org/mod"), false),
+      None
+    );
+  }
+
+  #[test]
+  fn https_only() {
+    let token = HostConfig {
+      token: Some(Secret::new("t0ken".to_string())),
+      ..Default::default()
+    };
+    let auth = auth(
+      vec![("example.com", token)],
+      "machine example.com login alice",
+    );
+    assert_eq!(
+      auth.authorization(&url("http://example.com/mod"), false),
+      None
+    );
+
+    let allowed = HostConfig {
+      allow_http_auth: true,
+      ..Default::default()
+    };
+    let auth = super::Auth::new(
+      [("lan".to_string(), allowed)],
+      Some(Netrc::parse("machine nas.lan login alice password secret")),
+    );
+    assert_eq!(
+      auth.authorization(&url("http://nas.lan/mod"), false),
+      basic("alice:secret")
+    );
+  }
+
+  #[test]
+  fn netrc_default_only_for_named_hosts() {
+    let auth = auth(
+      Vec::new(),
+      "machine example.com login alice password secret\ndefault login anonymous password me",
+    );
+    assert_eq!(
+      auth.authorization(&url("https://example.com/mod"), true),
+      basic("alice:secret")
+    );
+    assert_eq!(
+      auth.authorization(&url("https://example.org/mod"), false),
+      basic("anonymous:me")
+    );
+    assert_eq!(
+      auth.authorization(&url("https://example.org/mod"), true),
+      None
+    );
+  }
+}
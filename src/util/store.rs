@@ -0,0 +1,132 @@
+use std::{
+  path::PathBuf,
+  time::{Duration, Instant},
+};
+
+use redb::{Database, DatabaseError, ReadTransaction, ReadableDatabase, WriteTransaction};
+use tracing::info;
+
+use super::error::{LockError, StoreError};
+
+/// Other kmf processes only keep the database open for one transaction, so they are waited
+/// for this long even without `wait`
+const BUSY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Transactional embedded key-value store.
+///
+/// redb locks the file while it is open, so the database is opened for each transaction and
+/// closed right after, letting several kmf processes share it. Transactions run on the
+/// blocking pool.
+#[derive(Debug, Clone)]
+pub struct Store {
+  path: PathBuf,
+  /// Wait for other processes instead of failing with [`LockError::Locked`]
+  wait: bool,
+}
+
+impl Store {
+  pub fn new(path: PathBuf, wait: bool) -> Self {
+    Self { path, wait }
+  }
+
+  /// Open the database and run `f` on it in the blocking pool, waiting while another
+  /// process has it open
+  async fn with_database<T: Send + 'static>(
+    &self,
+    f: impl FnOnce(&Database) -> Result<T, StoreError> + Send + 'static,
+  ) -> Result<T, StoreError> {
+    let started = Instant::now();
+    let mut waiting = false;
+    let mut f = Some(f);
+    loop {
+      let path = self.path.to_owned();
+      let mut pending = f.take();
+      let (value, rest) =
+        tokio::task::spawn_blocking(move || match Database::create(path.as_path()) {
+          Ok(database) => (Some(pending.take().expect("f is pending")(&database)), None),
+          Err(DatabaseError::DatabaseAlreadyOpen) => (None, pending),
+          Err(err) => (Some(Err(err.into())), None),
+        })
+        .await
+        .expect("store task panicked");
+      if let Some(value) = value {
+        return value;
+      }
+      f = rest;
+      if !self.wait && started.elapsed() > BUSY_TIMEOUT {
+        return Err(
+          LockError::Locked {
+            path: self.path.to_owned(),
+          }
+          .into(),
+        );
+      }
+      if !waiting {
+        info!("waiting for another kmf to release {:?}", self.path);
+        waiting = true;
+      }
+      tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+  }
+
+  /// Run `f` in a read transaction
+  pub async fn read<T: Send + 'static>(
+    &self,
+    f: impl FnOnce(&ReadTransaction) -> Result<T, StoreError> + Send + 'static,
+  ) -> Result<T, StoreError> {
+    self
+      .with_database(move |database| f(&database.begin_read()?))
+      .await
+  }
+
+  /// Run `f` in a write transaction, committed only when `f` succeeds
+  pub async fn write<T: Send + 'static>(
+    &self,
+    f: impl FnOnce(&WriteTransaction) -> Result<T, StoreError> + Send + 'static,
+  ) -> Result<T, StoreError> {
+    self
+      .with_database(move |database| {
+        let transaction = database.begin_write()?;
+        let value = f(&transaction)?;
+        transaction.commit()?;
+        Ok(value)
+      })
+      .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use redb::TableDefinition;
+
+  use super::*;
+
+  const TABLE: TableDefinition<&str, u64> = TableDefinition::new("test");
+
+  #[tokio::test]
+  async fn fails_while_the_database_is_held() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.redb");
+    let store = Store::new(path.to_owned(), false);
+    store
+      .write(|transaction| {
+        transaction.open_table(TABLE)?.insert("a", 1)?;
+        Ok(())
+      })
+      .await
+      .unwrap();
+
+    let database = Database::create(path.as_path()).unwrap();
+    let read =
+      store.read(|transaction| Ok(transaction.open_table(TABLE)?.get("a")?.map(|x| x.value())));
+    assert!(matches!(
+      read.await,
+      Err(StoreError::Lock(LockError::Locked { .. }))
+    ));
+
+    drop(database);
+    let read =
+      store.read(|transaction| Ok(transaction.open_table(TABLE)?.get("a")?.map(|x| x.value())));
+    assert_eq!(read.await.unwrap(), Some(1));
+  }
+}